@@ -0,0 +1,24 @@
+//! Encode arbitrary Binding Requests into buffers of arbitrary size.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nyat_stun::{BindingRequest, Error, TransactionId};
+
+fuzz_target!(|input: (u16, Option<u16>, TransactionId, u16)| {
+    let (padding, response_port, tx_id, buf_len) = input;
+    let req = BindingRequest {
+        padding,
+        response_port,
+    };
+    let mut buf = vec![0xa5; usize::from(buf_len)];
+    match req.encode(&tx_id, &mut buf) {
+        Ok(len) => {
+            assert_eq!(len, req.encoded_len());
+            assert_eq!(nyat_stun::message_len(&buf[..len]), Some(len));
+            assert_eq!(buf[8..20], tx_id);
+        }
+        Err(Error::BufferTooSmall) => assert!(buf.len() < req.encoded_len()),
+        Err(e) => panic!("unexpected error: {e}"),
+    }
+});
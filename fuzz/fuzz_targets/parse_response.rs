@@ -0,0 +1,20 @@
+//! Arbitrary bytes as a STUN response, with the transaction ID taken from
+//! the input so that parsing gets past the header.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nyat_stun::{HEADER_SIZE, TransactionId};
+
+fuzz_target!(|data: &[u8]| {
+    let mut tx_id = TransactionId::default();
+    if let Some(id) = data.get(8..HEADER_SIZE) {
+        tx_id.copy_from_slice(id);
+    }
+    if nyat_stun::parse_response(data, &tx_id).is_ok() {
+        assert!(data.len() >= HEADER_SIZE);
+    }
+    if let Some(len) = nyat_stun::message_len(data) {
+        assert!(len >= HEADER_SIZE);
+    }
+});
@@ -33,6 +33,20 @@ pub(crate) enum StunError {
     /// The STUN response transaction ID did not match the request.
     #[error("STUN transaction ID mismatch")]
     TransactionIdMismatch,
+
+    /// The server answered with a Binding Error Response.
+    #[error("STUN error response {0}")]
+    ErrorResponse(u16),
+}
+
+impl From<nyat_stun::Error> for StunError {
+    fn from(e: nyat_stun::Error) -> Self {
+        match e {
+            nyat_stun::Error::TransactionIdMismatch => Self::TransactionIdMismatch,
+            nyat_stun::Error::ErrorResponse(code) => Self::ErrorResponse(code),
+            _ => Self::Malformed,
+        }
+    }
 }
 
 /// Top-level error returned by mapper operations.
@@ -58,6 +72,11 @@ pub enum Error {
     #[error("STUN transaction ID mismatch")]
     StunTransactionIdMismatch,
 
+    /// The STUN server answered with an error code, e.g. `420` when it does
+    /// not support a requested attribute.
+    #[error("STUN error response {0}")]
+    StunErrorResponse(u16),
+
     /// The system DNS resolver returned an error.
     #[error("DNS lookup failed")]
     DnsResolve(#[source] io::Error),
@@ -77,6 +96,20 @@ pub enum Error {
     /// Keepalive I/O failed (connection likely broken).
     #[error("keepalive failed")]
     Keepalive(#[source] io::Error),
+
+    /// A verification peer answered with a wrong or malformed nonce.
+    #[error("peer verification echo mismatch")]
+    VerifyMismatch,
+
+    /// Two STUN probes from the same local port saw different public
+    /// addresses, e.g. a moving mapping, or a symmetric NAT when the probes
+    /// went to different servers.
+    #[error("mapping is not stable across probes")]
+    MappingUnstable,
+
+    /// `run` was called on a mapper whose `run` is already active.
+    #[error("mapper is already running")]
+    AlreadyRunning,
 }
 
 impl Error {
@@ -84,7 +117,7 @@ impl Error {
     pub fn is_recoverable(&self) -> bool {
         !matches!(
             self,
-            Self::Socket(_) | Self::DnsResolve(_) | Self::AddrNotFound
+            Self::Socket(_) | Self::DnsResolve(_) | Self::AddrNotFound | Self::AlreadyRunning
         )
     }
 }
@@ -97,6 +130,7 @@ impl From<StunError> for Error {
             StunError::ResponseTooLarge => Self::StunResponseTooLarge,
             StunError::Network(e) => Self::StunNetwork(e),
             StunError::TransactionIdMismatch => Self::StunTransactionIdMismatch,
+            StunError::ErrorResponse(code) => Self::StunErrorResponse(code),
         }
     }
 }
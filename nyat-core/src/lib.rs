@@ -34,6 +34,8 @@ compile_error!("at least one of the `tcp` or `udp` features must be enabled");
 mod error;
 pub mod mapper;
 pub mod net;
-mod stun;
+pub mod probe;
+pub mod stun;
+pub mod verify;
 
 pub use error::Error;
@@ -6,13 +6,35 @@
 
 use std::net::SocketAddr;
 
+mod alert;
 mod builder;
+mod channel;
+#[cfg(feature = "tower")]
+mod connector;
+mod damping;
+mod event;
+#[cfg(feature = "udp")]
+mod pacing;
+mod plan;
+mod port_scan;
+#[cfg(feature = "tcp")]
+mod takeover;
 #[cfg(feature = "tcp")]
 mod tcp;
 #[cfg(feature = "udp")]
 mod udp;
 
+pub use alert::{Alert, AlertKind, AlertMonitor, AlertRules};
 pub use builder::MapperBuilder;
+pub use channel::{ChannelHandler, MappingReceiver, Overflow, channel};
+#[cfg(feature = "tower")]
+pub use connector::MappedConnector;
+pub use damping::FlapDamping;
+pub(crate) use event::EventBus;
+pub use event::{Ipv6ChangePolicy, MappingEvent};
+pub use plan::{Plan, PlannedConnection};
+#[cfg(feature = "tcp")]
+pub use takeover::{BorrowedStream, Takeover};
 #[cfg(feature = "tcp")]
 pub use tcp::TcpMapper;
 #[cfg(feature = "udp")]
@@ -37,12 +59,27 @@ impl MappingInfo {
     }
 }
 
-/// Called when the discovered public address changes.
+/// Subscriber to mapper events.
+///
+/// Most handlers only care about address changes and implement
+/// [`on_change`](Self::on_change); observers such as metrics override
+/// [`on_event`](Self::on_event) to see every probe and reconnect.
+/// Several handlers can be combined as a tuple `(A, B)`.
 ///
 /// Automatically implemented for `FnMut(MappingInfo)` closures.
 pub trait MappingHandler: Send {
     /// Invoked once each time the public socket address changes.
     fn on_change(&mut self, info: MappingInfo);
+
+    /// Invoked for every [`MappingEvent`].
+    ///
+    /// The default forwards [`MappingEvent::AddressChanged`] to
+    /// [`on_change`](Self::on_change) and ignores the rest.
+    fn on_event(&mut self, event: &MappingEvent<'_>) {
+        if let MappingEvent::AddressChanged(info) = event {
+            self.on_change(*info);
+        }
+    }
 }
 
 impl<F: FnMut(MappingInfo) + Send> MappingHandler for F {
@@ -51,6 +88,18 @@ impl<F: FnMut(MappingInfo) + Send> MappingHandler for F {
     }
 }
 
+impl<A: MappingHandler, B: MappingHandler> MappingHandler for (A, B) {
+    fn on_change(&mut self, info: MappingInfo) {
+        self.0.on_change(info);
+        self.1.on_change(info);
+    }
+
+    fn on_event(&mut self, event: &MappingEvent<'_>) {
+        self.0.on_event(event);
+        self.1.on_event(event);
+    }
+}
+
 /// Mapper container
 #[cfg(all(feature = "tcp", feature = "udp"))]
 #[derive(Debug)]
@@ -78,4 +127,13 @@ impl Mapper {
             Self::Udp(mapper) => mapper.run(handler).await,
         }
     }
+
+    /// Resolve DNS and describe the sockets and connections `run` would
+    /// create, without sending any traffic.
+    pub async fn dry_run(&self) -> Result<Plan, crate::Error> {
+        match self {
+            Self::Tcp(mapper) => mapper.dry_run().await,
+            Self::Udp(mapper) => mapper.dry_run().await,
+        }
+    }
 }
@@ -0,0 +1,221 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use super::{MappingHandler, MappingInfo};
+
+const RATE_WINDOW: Duration = Duration::from_secs(3600);
+
+/// Conditions checked by an [`AlertMonitor`]. All are off by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AlertRules {
+    max_changes_per_hour: Option<usize>,
+    port_mismatch: bool,
+    prefix_v4: Option<u8>,
+    prefix_v6: Option<u8>,
+}
+
+impl AlertRules {
+    /// No conditions.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            max_changes_per_hour: None,
+            port_mismatch: false,
+            prefix_v4: None,
+            prefix_v6: None,
+        }
+    }
+
+    /// Alert when the address changes more than `max` times within an hour.
+    #[must_use]
+    pub const fn max_changes_per_hour(mut self, max: usize) -> Self {
+        self.max_changes_per_hour = Some(max);
+        self
+    }
+
+    /// Alert when the public port differs from the local port, i.e. the
+    /// NAT stopped preserving ports.
+    #[must_use]
+    pub const fn port_mismatch(mut self, enabled: bool) -> Self {
+        self.port_mismatch = enabled;
+        self
+    }
+
+    /// Alert when a new IPv4 address is outside the previous one's `/len`
+    /// (clamped to 32), e.g. after moving to another ISP or CGNAT pool.
+    #[must_use]
+    pub const fn prefix_v4(mut self, len: u8) -> Self {
+        self.prefix_v4 = Some(if len > 32 { 32 } else { len });
+        self
+    }
+
+    /// Alert when a new IPv6 address is outside the previous one's `/len`
+    /// (clamped to 128).
+    #[must_use]
+    pub const fn prefix_v6(mut self, len: u8) -> Self {
+        self.prefix_v6 = Some(if len > 128 { 128 } else { len });
+        self
+    }
+
+    /// Whether no condition is set.
+    pub const fn is_empty(&self) -> bool {
+        self.max_changes_per_hour.is_none()
+            && !self.port_mismatch
+            && self.prefix_v4.is_none()
+            && self.prefix_v6.is_none()
+    }
+}
+
+/// What an [`Alert`] was raised for.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlertKind {
+    /// `changes` address changes within the last hour, above the limit.
+    ChangeRate { changes: usize },
+    /// The public port differs from the local port.
+    PortMismatch,
+    /// The address moved out of `from`'s `/prefix_len`.
+    PrefixChanged { from: IpAddr, prefix_len: u8 },
+}
+
+/// An anomaly in the observed mapping.
+#[derive(Debug, Clone, Copy)]
+pub struct Alert {
+    pub kind: AlertKind,
+    /// The mapping that raised the alert.
+    pub info: MappingInfo,
+}
+
+impl Alert {
+    /// Short name of the condition: `changes`, `port` or `prefix`.
+    pub const fn name(&self) -> &'static str {
+        match self.kind {
+            AlertKind::ChangeRate { .. } => "changes",
+            AlertKind::PortMismatch => "port",
+            AlertKind::PrefixChanged { .. } => "prefix",
+        }
+    }
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let MappingInfo {
+            pub_addr,
+            local_addr,
+            ..
+        } = self.info;
+        match self.kind {
+            AlertKind::ChangeRate { changes } => {
+                write!(
+                    f,
+                    "{changes} address changes in the last hour, now {pub_addr}"
+                )
+            }
+            AlertKind::PortMismatch => write!(
+                f,
+                "public port {} differs from local port {}",
+                pub_addr.port(),
+                local_addr.port()
+            ),
+            AlertKind::PrefixChanged { from, prefix_len } => write!(
+                f,
+                "address moved from {from} to {} (outside /{prefix_len})",
+                pub_addr.ip()
+            ),
+        }
+    }
+}
+
+/// Observer raising [`Alert`]s from [`AlertRules`].
+///
+/// Watches address changes and calls `on_alert`; combine it with the main
+/// handler as `mapper.run(&mut (handler, monitor))`. Each condition fires
+/// when it starts to hold, not on every change while it keeps holding.
+pub struct AlertMonitor<F> {
+    rules: AlertRules,
+    on_alert: F,
+    changes: VecDeque<Instant>,
+    last: Option<MappingInfo>,
+    rate_alerted: bool,
+}
+
+impl<F: FnMut(&Alert) + Send> AlertMonitor<F> {
+    pub fn new(rules: AlertRules, on_alert: F) -> Self {
+        Self {
+            rules,
+            on_alert,
+            changes: VecDeque::new(),
+            last: None,
+            rate_alerted: false,
+        }
+    }
+
+    fn alert(&mut self, kind: AlertKind, info: MappingInfo) {
+        (self.on_alert)(&Alert { kind, info });
+    }
+}
+
+impl<F: FnMut(&Alert) + Send> MappingHandler for AlertMonitor<F> {
+    fn on_change(&mut self, info: MappingInfo) {
+        let last = self.last.replace(info);
+
+        // neither the first mapping nor a repeat after reconnecting is a change
+        if let Some(max) = self.rules.max_changes_per_hour
+            && last.is_some_and(|last| last.pub_addr != info.pub_addr)
+        {
+            let now = Instant::now();
+            self.changes.push_back(now);
+            while self
+                .changes
+                .front()
+                .is_some_and(|&t| now.duration_since(t) > RATE_WINDOW)
+            {
+                self.changes.pop_front();
+            }
+            let changes = self.changes.len();
+            if changes <= max {
+                self.rate_alerted = false;
+            } else if !self.rate_alerted {
+                self.rate_alerted = true;
+                self.alert(AlertKind::ChangeRate { changes }, info);
+            }
+        }
+
+        if self.rules.port_mismatch {
+            let mismatch = |i: &MappingInfo| i.pub_addr.port() != i.local_addr.port();
+            if mismatch(&info) && !last.as_ref().is_some_and(mismatch) {
+                self.alert(AlertKind::PortMismatch, info);
+            }
+        }
+
+        if let Some(last) = last {
+            let (from, to) = (last.pub_addr.ip(), info.pub_addr.ip());
+            let prefix_len = match to {
+                IpAddr::V4(_) => self.rules.prefix_v4,
+                IpAddr::V6(_) => self.rules.prefix_v6,
+            };
+            if let Some(prefix_len) = prefix_len
+                && from.is_ipv4() == to.is_ipv4()
+                && !same_prefix(from, to, prefix_len)
+            {
+                self.alert(AlertKind::PrefixChanged { from, prefix_len }, info);
+            }
+        }
+    }
+}
+
+fn same_prefix(a: IpAddr, b: IpAddr, len: u8) -> bool {
+    match (a, b) {
+        (IpAddr::V4(a), IpAddr::V4(b)) => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(len)).unwrap_or(0);
+            u32::from(a) & mask == u32::from(b) & mask
+        }
+        (IpAddr::V6(a), IpAddr::V6(b)) => {
+            let mask = u128::MAX.checked_shl(128 - u32::from(len)).unwrap_or(0);
+            u128::from(a) & mask == u128::from(b) & mask
+        }
+        _ => false,
+    }
+}
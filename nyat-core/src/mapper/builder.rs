@@ -2,9 +2,11 @@
 use crate::mapper::TcpMapper;
 #[cfg(feature = "udp")]
 use crate::mapper::UdpMapper;
+use crate::mapper::{FlapDamping, Ipv6ChangePolicy};
 use crate::net::{LocalAddr, RemoteAddr};
 #[cfg(feature = "udp")]
 use std::num::NonZeroUsize;
+use std::ops::RangeInclusive;
 use std::time::Duration;
 
 #[doc(hidden)]
@@ -47,8 +49,15 @@ pub struct TcpConfig {
 #[derive(Debug)]
 pub struct MapperBuilder<S> {
     pub(super) local: LocalAddr,
+    pub(super) stun_local: Option<LocalAddr>,
     pub(super) stun: RemoteAddr,
     pub(super) interval: Duration,
+    pub(super) stun_timeout: Duration,
+    pub(super) verify_responder: bool,
+    pub(super) ipv6_policy: Ipv6ChangePolicy,
+    pub(super) flap_damping: Option<FlapDamping>,
+    pub(super) port_fallback: Option<RangeInclusive<u16>>,
+    pub(super) port_check: Option<RemoteAddr>,
     pub(super) config: S,
 }
 
@@ -61,8 +70,15 @@ impl MapperBuilder<UdpConfig> {
     pub const fn new_udp(local: LocalAddr, stun_addr: RemoteAddr) -> Self {
         Self {
             local,
+            stun_local: None,
             stun: stun_addr,
             interval: Duration::from_secs(5),
+            stun_timeout: crate::stun::TIMEOUT_DURATION,
+            verify_responder: false,
+            ipv6_policy: Ipv6ChangePolicy::Report,
+            flap_damping: None,
+            port_fallback: None,
+            port_check: None,
             config: UdpConfig {
                 check_per_tick: NonZeroUsize::new(5).unwrap(),
             },
@@ -94,8 +110,15 @@ impl MapperBuilder<TcpConfig> {
     pub const fn new_tcp(local: LocalAddr, stun_addr: RemoteAddr, ka_remote: RemoteAddr) -> Self {
         Self {
             local,
+            stun_local: None,
             stun: stun_addr,
             interval: Duration::from_secs(30),
+            stun_timeout: crate::stun::TIMEOUT_DURATION,
+            verify_responder: false,
+            ipv6_policy: Ipv6ChangePolicy::Report,
+            flap_damping: None,
+            port_fallback: None,
+            port_check: None,
             config: TcpConfig { ka_remote },
         }
     }
@@ -116,4 +139,88 @@ impl<S> MapperBuilder<S> {
         self.interval = interval;
         self
     }
+
+    /// Set how long a STUN probe waits for its answer. Defaults to 30 s.
+    ///
+    /// UDP re-probes wait at most this long or their paced timeout (see
+    /// the crate README), whichever is shorter.
+    #[must_use]
+    pub const fn stun_timeout(mut self, timeout: Duration) -> Self {
+        self.stun_timeout = timeout;
+        self
+    }
+
+    /// Answer [peer verification](crate::verify) requests arriving at the
+    /// mapped port. Defaults to `false`.
+    ///
+    /// TCP mappers listen on the local port (with `SO_REUSEPORT`); UDP
+    /// mappers answer on their keepalive socket. The kernel spreads inbound
+    /// TCP connections across all listeners on a port, so a TCP responder
+    /// needs the port to itself: on Linux, running fails with
+    /// [`Error::Socket`](crate::Error::Socket) if another socket already
+    /// listens there.
+    #[must_use]
+    pub const fn verify_responder(mut self, enable: bool) -> Self {
+        self.verify_responder = enable;
+        self
+    }
+
+    /// Set how IPv6 changes confined to the interface identifier, flow
+    /// label or scope are reported. Defaults to [`Ipv6ChangePolicy::Report`].
+    #[must_use]
+    pub const fn ipv6_change_policy(mut self, policy: Ipv6ChangePolicy) -> Self {
+        self.ipv6_policy = policy;
+        self
+    }
+
+    /// Hold back session rebuilds while the interface flaps. Off by default.
+    #[must_use]
+    pub const fn flap_damping(mut self, damping: FlapDamping) -> Self {
+        self.flap_damping = Some(damping);
+        self
+    }
+
+    /// Treat the local port as a preference: before the first session, if
+    /// it cannot be bound or its mapping is not stable, try each port of
+    /// `ports` in order and keep the first one that works. Off by default.
+    ///
+    /// A port works when two STUN probes from it, a second apart, see the
+    /// same public address. Both go to the mapper's STUN server unless
+    /// [`port_fallback_check`](Self::port_fallback_check) names a second
+    /// one; a single server rejects ports whose mapping keeps moving, not
+    /// every symmetric NAT. A local port of `0` skips straight to `ports`.
+    /// The choice is reported as
+    /// [`MappingEvent::PortSelected`](super::MappingEvent::PortSelected)
+    /// and kept across reconnects; if no port works, `run` fails with the
+    /// last port's error.
+    #[must_use]
+    pub fn port_fallback(mut self, ports: RangeInclusive<u16>) -> Self {
+        self.port_fallback = Some(ports);
+        self
+    }
+
+    /// Send the second [`port_fallback`](Self::port_fallback) probe to
+    /// `stun` instead of the mapper's STUN server. A port then only works
+    /// if both servers see the same public address, which also rejects
+    /// symmetric (destination-dependent) mappings.
+    #[must_use]
+    pub fn port_fallback_check(mut self, stun: RemoteAddr) -> Self {
+        self.port_check = Some(stun);
+        self
+    }
+
+    /// Create the STUN socket from `local` instead of the keepalive
+    /// socket's [`LocalAddr`], e.g. to probe over `wan0` while keepalives
+    /// leave through `wan1`.
+    ///
+    /// The STUN socket takes its interface, fwmark, DNS servers and bind
+    /// address from `local`; a port of `0` means the keepalive socket's
+    /// port, so both still share one local port. Reported
+    /// [`MappingInfo::local_addr`](super::MappingInfo::local_addr) is the
+    /// keepalive socket's address. Defaults to the keepalive `LocalAddr`.
+    #[must_use]
+    pub fn stun_local(mut self, local: LocalAddr) -> Self {
+        self.stun_local = Some(local);
+        self
+    }
 }
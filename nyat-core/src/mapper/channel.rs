@@ -0,0 +1,150 @@
+use std::collections::VecDeque;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::Notify;
+
+use crate::mapper::{MappingHandler, MappingInfo};
+
+/// What to do when the queue is full and a new mapping arrives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Discard the oldest queued mapping to make room.
+    DropOldest,
+    /// Overwrite the newest queued mapping, so the consumer always ends on
+    /// the latest address without the queue growing.
+    Coalesce,
+}
+
+#[derive(Debug)]
+struct Shared {
+    queue: Mutex<VecDeque<MappingInfo>>,
+    capacity: usize,
+    overflow: Overflow,
+    dropped: AtomicU64,
+    closed: AtomicBool,
+    notify: Notify,
+}
+
+/// Create a bounded mapping channel.
+///
+/// The returned [`ChannelHandler`] is passed to `run`; the
+/// [`MappingReceiver`] is consumed elsewhere. The queue never holds more
+/// than `capacity` entries: when the consumer stalls, `overflow` decides
+/// which entry is discarded, and the mapper itself never blocks.
+///
+/// # Examples
+///
+/// ```no_run
+/// use nyat_core::mapper::{MapperBuilder, Overflow, channel};
+/// use nyat_core::net::{LocalAddr, RemoteAddr};
+/// use std::num::NonZeroUsize;
+///
+/// # async fn example() -> Result<(), nyat_core::Error> {
+/// let mapper = MapperBuilder::new_udp(
+///     LocalAddr::new("0.0.0.0:0".parse().unwrap()),
+///     RemoteAddr::from_host("stun.l.google.com", 19302, None),
+/// )
+/// .build();
+///
+/// let (mut handler, mut rx) = channel(NonZeroUsize::new(8).unwrap(), Overflow::Coalesce);
+/// let consumer = async move {
+///     while let Some(info) = rx.recv().await {
+///         println!("{}", info.pub_addr);
+///     }
+/// };
+/// let (res, ()) = tokio::join!(mapper.run(&mut handler), consumer);
+/// res
+/// # }
+/// ```
+pub fn channel(capacity: NonZeroUsize, overflow: Overflow) -> (ChannelHandler, MappingReceiver) {
+    let shared = Arc::new(Shared {
+        queue: Mutex::new(VecDeque::with_capacity(capacity.get())),
+        capacity: capacity.get(),
+        overflow,
+        dropped: AtomicU64::new(0),
+        closed: AtomicBool::new(false),
+        notify: Notify::new(),
+    });
+    (
+        ChannelHandler {
+            shared: shared.clone(),
+        },
+        MappingReceiver { shared },
+    )
+}
+
+/// Sending half of [`channel`]; a [`MappingHandler`] that never blocks.
+///
+/// Dropping it closes the channel once the queue is drained.
+#[derive(Debug)]
+pub struct ChannelHandler {
+    shared: Arc<Shared>,
+}
+
+impl MappingHandler for ChannelHandler {
+    fn on_change(&mut self, info: MappingInfo) {
+        let shared = &self.shared;
+        {
+            let mut queue = shared.queue.lock().unwrap_or_else(|e| e.into_inner());
+            if queue.len() >= shared.capacity {
+                shared.dropped.fetch_add(1, Ordering::Relaxed);
+                match shared.overflow {
+                    Overflow::DropOldest => {
+                        queue.pop_front();
+                    }
+                    Overflow::Coalesce => {
+                        queue.pop_back();
+                    }
+                }
+            }
+            queue.push_back(info);
+        }
+        shared.notify.notify_one();
+    }
+}
+
+impl Drop for ChannelHandler {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Release);
+        self.shared.notify.notify_one();
+    }
+}
+
+/// Receiving half of [`channel`].
+#[derive(Debug)]
+pub struct MappingReceiver {
+    shared: Arc<Shared>,
+}
+
+impl MappingReceiver {
+    /// Wait for the next mapping.
+    ///
+    /// Returns `None` once the [`ChannelHandler`] is dropped and the queue is empty.
+    pub async fn recv(&mut self) -> Option<MappingInfo> {
+        loop {
+            if let Some(info) = self.try_recv() {
+                return Some(info);
+            }
+            if self.shared.closed.load(Ordering::Acquire) {
+                return self.try_recv();
+            }
+            self.shared.notify.notified().await;
+        }
+    }
+
+    /// Take the next queued mapping without waiting.
+    pub fn try_recv(&mut self) -> Option<MappingInfo> {
+        self.shared
+            .queue
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .pop_front()
+    }
+
+    /// Number of mappings discarded so far because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+}
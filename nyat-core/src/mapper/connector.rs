@@ -0,0 +1,150 @@
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use http::Uri;
+use http::uri::Scheme;
+use tokio::net::TcpStream;
+use tokio::sync::watch;
+
+use crate::error::Error;
+use crate::net::{IpVer, LocalAddr, Protocol};
+
+type ConnectFuture = Pin<Box<dyn Future<Output = Result<TcpStream, Error>> + Send>>;
+
+/// Outbound connector dialing from a [`TcpMapper`](super::TcpMapper)'s
+/// local port, obtained from
+/// [`TcpMapper::connector`](super::TcpMapper::connector).
+///
+/// Implements [`tower_service::Service`] for [`Uri`] (the hyper-util
+/// connector shape) and [`SocketAddr`]. Every connection gets a fresh socket
+/// bound to the mapper's current local address with the same options
+/// (`SO_REUSEPORT`, fwmark, interface), so on NATs with endpoint-independent
+/// mapping it leaves through the mapping nyat maintains — e.g. for TCP hole
+/// punching over HTTP.
+///
+/// Calls wait until the mapper has a session up: while it rebuilds a
+/// torn-down session, and after `run` returns, they wait for the next one.
+/// A connection to the keepalive or STUN server itself fails, since that
+/// 4-tuple is taken.
+///
+/// hyper needs its own I/O traits; wrap the stream with
+/// `hyper_util::rt::TokioIo` (e.g. `tower::ServiceExt::map_response`).
+#[derive(Debug, Clone)]
+pub struct MappedConnector {
+    pub(super) local: LocalAddr,
+    pub(super) bound: watch::Receiver<Option<SocketAddr>>,
+}
+
+impl MappedConnector {
+    /// Connect to `remote` from the mapped local port.
+    pub async fn connect(&self, remote: SocketAddr) -> Result<TcpStream, Error> {
+        let local_addr = self.local_addr().await?;
+        self.connect_from(local_addr, remote).await
+    }
+
+    async fn connect_uri(&self, uri: Uri) -> Result<TcpStream, Error> {
+        let host = uri
+            .host()
+            .ok_or_else(|| {
+                Error::Connection(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "URI has no host",
+                ))
+            })?
+            .trim_start_matches('[')
+            .trim_end_matches(']');
+        let port = uri
+            .port_u16()
+            .unwrap_or(if uri.scheme() == Some(&Scheme::HTTPS) {
+                443
+            } else {
+                80
+            });
+
+        let local_addr = self.local_addr().await?;
+        let ver = if local_addr.is_ipv6() {
+            IpVer::V6
+        } else {
+            IpVer::V4
+        };
+        let remote = self.local.resolve(host, port, Some(ver)).await?;
+        self.connect_from(local_addr, remote).await
+    }
+
+    async fn connect_from(
+        &self,
+        local_addr: SocketAddr,
+        remote: SocketAddr,
+    ) -> Result<TcpStream, Error> {
+        let socket = self
+            .local
+            .socket_from_addr(local_addr, Protocol::Tcp)
+            .await
+            .map_err(Error::Socket)?;
+        crate::net::connect_remote(socket, remote)
+            .await
+            .map_err(Error::Connection)
+    }
+
+    /// The local address of the mapper's current session, once there is one.
+    async fn local_addr(&self) -> Result<SocketAddr, Error> {
+        let mut bound = self.bound.clone();
+        let addr = bound.wait_for(Option::is_some).await.map_err(|_| {
+            Error::Connection(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "mapper dropped",
+            ))
+        })?;
+        Ok(addr.unwrap())
+    }
+}
+
+impl tower_service::Service<Uri> for MappedConnector {
+    type Response = TcpStream;
+    type Error = Error;
+    type Future = ConnectFuture;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let this = self.clone();
+        Box::pin(async move { this.connect_uri(uri).await })
+    }
+}
+
+impl tower_service::Service<SocketAddr> for MappedConnector {
+    type Response = TcpStream;
+    type Error = Error;
+    type Future = ConnectFuture;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, remote: SocketAddr) -> Self::Future {
+        let this = self.clone();
+        Box::pin(async move { this.connect(remote).await })
+    }
+}
+
+/// Publishes a session's local address to the connectors for as long as
+/// the session lasts.
+pub(super) struct Bound<'a>(&'a watch::Sender<Option<SocketAddr>>);
+
+impl<'a> Bound<'a> {
+    pub(super) fn set(bound: &'a watch::Sender<Option<SocketAddr>>, addr: SocketAddr) -> Self {
+        bound.send_replace(Some(addr));
+        Self(bound)
+    }
+}
+
+impl Drop for Bound<'_> {
+    fn drop(&mut self) {
+        self.0.send_replace(None);
+    }
+}
@@ -0,0 +1,118 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::mapper::{EventBus, MappingEvent, MappingHandler};
+use crate::net::LocalAddr;
+
+/// How often the interface is checked while settling.
+const POLL: Duration = Duration::from_secs(1);
+/// Wait before rebuilding a session when not damped.
+const REBUILD_DELAY: Duration = Duration::from_secs(5);
+
+/// Interface flap damping, set with
+/// [`MapperBuilder::flap_damping`](super::MapperBuilder::flap_damping).
+///
+/// When sessions are torn down `teardowns` times within `window` — e.g. an
+/// LTE modem bouncing its link — the mapper stops rebuilding and waits until
+/// the bound interface has been up, running and addressed for `settle`
+/// without interruption, then probes immediately. Without an interface
+/// binding it simply waits `settle`. Teardowns include failed session
+/// setups, which still count towards the mapper's retry limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlapDamping {
+    teardowns: usize,
+    window: Duration,
+    settle: Duration,
+}
+
+impl FlapDamping {
+    /// Damp after 3 teardowns within 60 s, until the interface has been
+    /// stable for `settle`.
+    #[must_use]
+    pub const fn new(settle: Duration) -> Self {
+        Self {
+            teardowns: 3,
+            window: Duration::from_secs(60),
+            settle,
+        }
+    }
+
+    /// Damp after `teardowns` teardowns (at least 1) within `window`.
+    #[must_use]
+    pub const fn threshold(mut self, teardowns: usize, window: Duration) -> Self {
+        self.teardowns = if teardowns == 0 { 1 } else { teardowns };
+        self.window = window;
+        self
+    }
+}
+
+/// Per-run damping state.
+#[derive(Debug)]
+pub(super) struct Damper {
+    config: Option<FlapDamping>,
+    teardowns: VecDeque<Instant>,
+}
+
+impl Damper {
+    pub(super) const fn new(config: Option<FlapDamping>) -> Self {
+        Self {
+            config,
+            teardowns: VecDeque::new(),
+        }
+    }
+
+    /// Wait before rebuilding a torn-down session: a fixed delay, or while
+    /// damped until the interfaces of `locals` have settled.
+    pub(super) async fn before_rebuild<H: MappingHandler>(
+        &mut self,
+        bus: &mut EventBus<'_, H>,
+        locals: &[&LocalAddr],
+    ) {
+        if self.teardown() {
+            bus.emit(MappingEvent::FlapDamping(true));
+            self.settle(locals).await;
+            bus.emit(MappingEvent::FlapDamping(false));
+        } else {
+            tokio::time::sleep(REBUILD_DELAY).await;
+        }
+    }
+
+    /// Record a teardown. Returns whether the rebuild should be damped.
+    fn teardown(&mut self) -> bool {
+        let Some(config) = self.config else {
+            return false;
+        };
+        let now = Instant::now();
+        self.teardowns.push_back(now);
+        while self
+            .teardowns
+            .front()
+            .is_some_and(|&t| now.duration_since(t) > config.window)
+        {
+            self.teardowns.pop_front();
+        }
+        self.teardowns.len() >= config.teardowns
+    }
+
+    /// Wait until every interface the mapper binds to has been stable for
+    /// the settle period, then forget past teardowns.
+    async fn settle(&mut self, locals: &[&LocalAddr]) {
+        let Some(config) = self.config else {
+            return;
+        };
+        let mut stable_since = Instant::now();
+        loop {
+            if !locals.iter().all(|l| l.iface_up()) {
+                stable_since = Instant::now();
+            }
+            let stable_for = stable_since.elapsed();
+            if stable_for >= config.settle {
+                break;
+            }
+            tokio::time::sleep((config.settle - stable_for).min(POLL)).await;
+        }
+        self.teardowns.clear();
+    }
+}
@@ -0,0 +1,98 @@
+use std::net::SocketAddr;
+
+use crate::error::Error;
+use crate::mapper::{MappingHandler, MappingInfo};
+
+/// Mapper lifecycle event, delivered to [`MappingHandler::on_event`].
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy)]
+pub enum MappingEvent<'a> {
+    /// A STUN probe (or session setup including one) is starting.
+    ProbeStarted,
+    /// A STUN probe returned a mapping, changed or not.
+    ProbeSucceeded(MappingInfo),
+    /// A STUN probe or session setup failed.
+    ProbeFailed(&'a Error),
+    /// The session ended and is about to be rebuilt.
+    Reconnect,
+    /// The public address differs from the last one reported.
+    AddressChanged(MappingInfo),
+    /// UDP only: sustained probe loss started (`true`) or ended (`false`)
+    /// fast re-probing with tighter timeouts.
+    LossRecovery(bool),
+    /// Sessions were torn down too often ([`FlapDamping`](super::FlapDamping)):
+    /// rebuilding is held back until the interface settles (`true`), or it
+    /// settled and the session is rebuilt now (`false`).
+    FlapDamping(bool),
+    /// With [`port_fallback`](super::MapperBuilder::port_fallback): the
+    /// local port chosen before the first session, `preferred` being the
+    /// configured one (`0` if ephemeral).
+    PortSelected { preferred: u16, selected: u16 },
+}
+
+/// How to treat a public IPv6 address change confined to the interface
+/// identifier (low 64 bits), flow label or scope id, with the same /64
+/// prefix and port.
+///
+/// Hosts with several equivalent global addresses (privacy extensions,
+/// multiple SLAAC addresses) may be mapped from a different one on each
+/// probe; such changes are usually noise.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Ipv6ChangePolicy {
+    /// Report as [`MappingEvent::AddressChanged`].
+    #[default]
+    Report,
+    /// Keep the previously reported address and stay silent.
+    Ignore,
+}
+
+impl Ipv6ChangePolicy {
+    /// Whether moving from `old` to `new` should be reported.
+    fn reports(self, old: SocketAddr, new: SocketAddr) -> bool {
+        match (self, old, new) {
+            (Self::Ignore, SocketAddr::V6(old), SocketAddr::V6(new)) => {
+                old.port() != new.port() || old.ip().segments()[..4] != new.ip().segments()[..4]
+            }
+            _ => old != new,
+        }
+    }
+}
+
+/// Single path from the mapper loops to the handler.
+///
+/// Owns the "has the address changed" state so every mapper reports
+/// changes identically.
+pub(crate) struct EventBus<'h, H> {
+    handler: &'h mut H,
+    current: Option<SocketAddr>,
+    ipv6_policy: Ipv6ChangePolicy,
+}
+
+impl<'h, H: MappingHandler> EventBus<'h, H> {
+    pub(crate) fn new(handler: &'h mut H, ipv6_policy: Ipv6ChangePolicy) -> Self {
+        Self {
+            handler,
+            current: None,
+            ipv6_policy,
+        }
+    }
+
+    pub(crate) fn emit(&mut self, event: MappingEvent<'_>) {
+        self.handler.on_event(&event);
+    }
+
+    /// Report a successful probe, followed by `AddressChanged` if the public
+    /// address moved (subject to the [`Ipv6ChangePolicy`]).
+    pub(crate) fn probe_succeeded(&mut self, info: MappingInfo) {
+        self.emit(MappingEvent::ProbeSucceeded(info));
+        let changed = match self.current {
+            Some(current) => self.ipv6_policy.reports(current, info.pub_addr),
+            None => true,
+        };
+        if changed {
+            self.current = Some(info.pub_addr);
+            self.emit(MappingEvent::AddressChanged(info));
+        }
+    }
+}
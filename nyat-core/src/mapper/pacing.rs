@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+/// Outcomes kept in the loss window.
+const WINDOW: u32 = 8;
+/// Probes needed before the loss ratio is trusted.
+const MIN_SAMPLES: u32 = 4;
+/// Consecutive successful probes that end recovery.
+const RECOVERY_EXIT: u32 = 3;
+/// Longest wait between ticks while recovering.
+const RECOVERY_TICK: Duration = Duration::from_secs(2);
+/// STUN timeout while recovering.
+const RECOVERY_TIMEOUT: Duration = Duration::from_secs(2);
+/// Longest STUN timeout otherwise.
+const MAX_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Loss-driven probe pacing for the UDP mapper.
+///
+/// Tracks the last [`WINDOW`] STUN probe outcomes. Once more than half of at
+/// least [`MIN_SAMPLES`] were lost, the mapper enters recovery: it probes on
+/// every tick, ticks at most every [`RECOVERY_TICK`] and gives up on a probe
+/// after [`RECOVERY_TIMEOUT`], until [`RECOVERY_EXIT`] probes in a row
+/// succeed. Outside recovery a probe may take up to one keepalive interval
+/// (clamped to [`RECOVERY_TIMEOUT`]..=[`MAX_TIMEOUT`]), so a single lost
+/// response does not stall keepalives.
+#[derive(Debug)]
+pub(super) struct Pacer {
+    /// Bit `i` set: the `i`-th most recent probe was lost.
+    lost: u32,
+    samples: u32,
+    recovering: bool,
+    streak: u32,
+}
+
+impl Pacer {
+    pub(super) const fn new() -> Self {
+        Self {
+            lost: 0,
+            samples: 0,
+            recovering: false,
+            streak: 0,
+        }
+    }
+
+    /// Record a probe outcome. Returns `Some(recovering)` when the pacing
+    /// mode changed.
+    pub(super) fn record(&mut self, ok: bool) -> Option<bool> {
+        self.lost = ((self.lost << 1) | u32::from(!ok)) & ((1 << WINDOW) - 1);
+        self.samples = (self.samples + 1).min(WINDOW);
+
+        if self.recovering {
+            self.streak = if ok { self.streak + 1 } else { 0 };
+            if self.streak >= RECOVERY_EXIT {
+                *self = Self::new();
+                return Some(false);
+            }
+        } else if self.samples >= MIN_SAMPLES && self.lost.count_ones() * 2 > self.samples {
+            self.recovering = true;
+            self.streak = 0;
+            return Some(true);
+        }
+        None
+    }
+
+    pub(super) const fn recovering(&self) -> bool {
+        self.recovering
+    }
+
+    /// Wait before the next tick.
+    pub(super) fn tick(&self, interval: Duration) -> Duration {
+        if self.recovering {
+            interval.min(RECOVERY_TICK)
+        } else {
+            interval
+        }
+    }
+
+    /// How long to wait for a STUN response.
+    pub(super) fn probe_timeout(&self, interval: Duration) -> Duration {
+        if self.recovering {
+            RECOVERY_TIMEOUT
+        } else {
+            interval.clamp(RECOVERY_TIMEOUT, MAX_TIMEOUT)
+        }
+    }
+}
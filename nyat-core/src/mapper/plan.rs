@@ -0,0 +1,83 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+/// What a mapper would create, with DNS resolved but no traffic sent.
+///
+/// Returned by `dry_run` on [`TcpMapper`](super::TcpMapper),
+/// [`UdpMapper`](super::UdpMapper) and [`Mapper`](super::Mapper).
+/// The [`Display`](fmt::Display) output is meant for change review.
+#[non_exhaustive]
+#[derive(Debug, Clone)]
+pub struct Plan {
+    /// `"tcp"` or `"udp"`.
+    pub protocol: &'static str,
+    /// Configured local bind address (port `0` means ephemeral).
+    pub local_addr: SocketAddr,
+    /// Ports tried if the local port does not work
+    /// ([`MapperBuilder::port_fallback`](super::MapperBuilder::port_fallback)).
+    pub port_fallback: Option<RangeInclusive<u16>>,
+    /// Socket options set on every socket, e.g. `SO_MARK=100`.
+    pub socket_options: Vec<String>,
+    /// Bind address of a separately configured STUN socket
+    /// ([`MapperBuilder::stun_local`](super::MapperBuilder::stun_local)).
+    pub stun_local_addr: Option<SocketAddr>,
+    /// Socket options of the separate STUN socket; empty if there is none.
+    pub stun_socket_options: Vec<String>,
+    /// Outbound connections, in the order they are made.
+    pub connections: Vec<PlannedConnection>,
+    /// Keepalive / STUN probe interval.
+    pub interval: Duration,
+    /// UDP only: STUN probe every N keepalive intervals.
+    pub check_per_tick: Option<usize>,
+}
+
+/// One outbound connection of a [`Plan`].
+#[non_exhaustive]
+#[derive(Debug, Clone)]
+pub struct PlannedConnection {
+    /// `"keepalive"`, `"stun"`, or `"check"` for the second server of
+    /// [`MapperBuilder::port_fallback_check`](super::MapperBuilder::port_fallback_check).
+    pub purpose: &'static str,
+    /// Remote as configured (domain or address).
+    pub remote: String,
+    /// Address the remote resolved to.
+    pub resolved: SocketAddr,
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} bind {} [{}]",
+            self.protocol,
+            self.local_addr,
+            self.socket_options.join(", ")
+        )?;
+        if let Some(ports) = &self.port_fallback {
+            write!(
+                f,
+                "\n  fallback  ports {}-{}, one with a stable mapping",
+                ports.start(),
+                ports.end()
+            )?;
+        }
+        if let Some(addr) = self.stun_local_addr {
+            write!(
+                f,
+                "\n  stun bind {} [{}]",
+                addr,
+                self.stun_socket_options.join(", ")
+            )?;
+        }
+        for c in &self.connections {
+            write!(f, "\n  {:<9} {} -> {}", c.purpose, c.remote, c.resolved)?;
+        }
+        write!(f, "\n  interval  {:?}", self.interval)?;
+        if let Some(n) = self.check_per_tick {
+            write!(f, " (STUN probe every {n} intervals)")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,117 @@
+//! Soft port preference: find a local port with a usable mapping.
+
+use std::io;
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+use tokio::time::timeout;
+
+use crate::error::Error;
+use crate::mapper::{EventBus, MappingEvent, MappingHandler, MappingInfo, PlannedConnection};
+use crate::net::{LocalAddr, Protocol, RemoteAddr};
+
+/// Per-probe timeout while scanning.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Gap between the two probes checking that a mapping is stable.
+const STABLE_GAP: Duration = Duration::from_secs(1);
+
+/// Return `local` with the first usable port: its own port (unless `0`),
+/// then each port of `fallback`.
+///
+/// A port is usable when it binds and two STUN probes from it, sent
+/// [`STABLE_GAP`] apart, see the same public address: the first goes to
+/// `stun`, the second to `check` if set, else `stun` again. Probes leave
+/// from `stun_local` if set, sharing the candidate port like the mapper's
+/// STUN socket. If no port is usable, the last port's error is returned.
+pub(super) async fn select<H: MappingHandler>(
+    local: &LocalAddr,
+    stun_local: Option<&LocalAddr>,
+    fallback: &RangeInclusive<u16>,
+    protocol: Protocol,
+    stun: &RemoteAddr,
+    check: Option<&RemoteAddr>,
+    bus: &mut EventBus<'_, H>,
+) -> Result<LocalAddr, Error> {
+    let preferred = local.addr().port();
+    let candidates = (preferred != 0)
+        .then_some(preferred)
+        .into_iter()
+        .chain(fallback.clone().filter(|&p| p != preferred));
+
+    let mut last_err = None;
+    for port in candidates {
+        let candidate = local.with_port(port);
+        let from = match stun_local {
+            Some(s) => s.with_port(s.share_port(candidate.addr()).port()),
+            None => candidate.clone(),
+        };
+        match stable(&from, protocol, stun, check.unwrap_or(stun)).await {
+            Ok(()) => {
+                bus.emit(MappingEvent::PortSelected {
+                    preferred,
+                    selected: port,
+                });
+                return Ok(candidate);
+            }
+            Err(e) => last_err = Some(e),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| {
+        Error::Socket(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "no port to try: fallback range is empty",
+        ))
+    }))
+}
+
+async fn stable(
+    local: &LocalAddr,
+    protocol: Protocol,
+    stun: &RemoteAddr,
+    check: &RemoteAddr,
+) -> Result<(), Error> {
+    let first = probe(local, protocol, stun).await?;
+    tokio::time::sleep(STABLE_GAP).await;
+    let second = probe(local, protocol, check).await?;
+    if first.pub_addr == second.pub_addr {
+        Ok(())
+    } else {
+        Err(Error::MappingUnstable)
+    }
+}
+
+async fn probe(
+    local: &LocalAddr,
+    protocol: Protocol,
+    stun: &RemoteAddr,
+) -> Result<MappingInfo, Error> {
+    let probe = async {
+        match protocol {
+            #[cfg(feature = "tcp")]
+            Protocol::Tcp => crate::probe::tcp(local, stun).await,
+            #[cfg(feature = "udp")]
+            Protocol::Udp => crate::probe::udp(local, stun).await,
+            #[cfg(not(feature = "udp"))]
+            Protocol::Udp => unreachable!("UDP probe without the udp feature"),
+        }
+    };
+    timeout(PROBE_TIMEOUT, probe)
+        .await
+        .unwrap_or_else(|_| Err(Error::Connection(io::ErrorKind::TimedOut.into())))
+}
+
+/// The check server of a [`select`], for dry runs.
+pub(super) async fn planned_check(
+    fallback: Option<&RangeInclusive<u16>>,
+    check: Option<&RemoteAddr>,
+    local: &LocalAddr,
+) -> Result<Option<PlannedConnection>, Error> {
+    let (Some(_), Some(check)) = (fallback, check) else {
+        return Ok(None);
+    };
+    Ok(Some(PlannedConnection {
+        purpose: "check",
+        remote: check.to_string(),
+        resolved: check.resolve(local).await?,
+    }))
+}
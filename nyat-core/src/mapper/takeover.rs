@@ -0,0 +1,96 @@
+use std::ops::{Deref, DerefMut};
+
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot, watch};
+
+/// Pending request for the keepalive stream.
+pub(super) type LendRequest = oneshot::Sender<BorrowedStream>;
+
+/// Handle for borrowing a [`TcpMapper`](super::TcpMapper)'s keepalive
+/// connection, obtained from [`TcpMapper::takeover`](super::TcpMapper::takeover).
+///
+/// The borrowed stream uses the exact mapped 5-tuple, so application data
+/// sent over it (e.g. a handshake with a peer that will connect back)
+/// leaves through the same NAT mapping nyat maintains.
+#[derive(Debug, Clone)]
+pub struct Takeover {
+    pub(super) tx: mpsc::Sender<LendRequest>,
+    pub(super) running: watch::Receiver<bool>,
+}
+
+impl Takeover {
+    /// Borrow the keepalive connection.
+    ///
+    /// While [`TcpMapper::run`](super::TcpMapper::run) is in progress, waits
+    /// for an established keepalive session. Keepalive requests pause while
+    /// the stream is borrowed and resume when the [`BorrowedStream`] is
+    /// dropped. Returns `None` at once if `run` is not in progress (not yet
+    /// started, already returned, or the mapper dropped), and as soon as
+    /// `run` returns while waiting.
+    ///
+    /// Anything the keepalive server sent before the borrow that the mapper
+    /// has not read yet (e.g. a pending `HEAD` response) is still in the
+    /// stream.
+    pub async fn borrow(&self) -> Option<BorrowedStream> {
+        let mut running = self.running.clone();
+        if !*running.borrow_and_update() {
+            return None;
+        }
+        let lend = async {
+            let (reply, lent) = oneshot::channel();
+            self.tx.send(reply).await.ok()?;
+            lent.await.ok()
+        };
+        tokio::select! {
+            stream = lend => stream,
+            _ = running.wait_for(|running| !running) => None,
+        }
+    }
+}
+
+/// Marks a mapper as running until dropped.
+pub(super) struct Running<'a>(&'a watch::Sender<bool>);
+
+impl<'a> Running<'a> {
+    pub(super) fn start(running: &'a watch::Sender<bool>) -> Self {
+        running.send_replace(true);
+        Self(running)
+    }
+}
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        self.0.send_replace(false);
+    }
+}
+
+/// The keepalive [`TcpStream`], lent out by [`Takeover::borrow`].
+///
+/// Dereferences to the stream. Dropping it hands the stream back to the mapper.
+#[derive(Debug)]
+pub struct BorrowedStream {
+    pub(super) stream: Option<TcpStream>,
+    pub(super) back: Option<oneshot::Sender<TcpStream>>,
+}
+
+impl Deref for BorrowedStream {
+    type Target = TcpStream;
+
+    fn deref(&self) -> &TcpStream {
+        self.stream.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for BorrowedStream {
+    fn deref_mut(&mut self) -> &mut TcpStream {
+        self.stream.as_mut().unwrap()
+    }
+}
+
+impl Drop for BorrowedStream {
+    fn drop(&mut self) {
+        if let (Some(stream), Some(back)) = (self.stream.take(), self.back.take()) {
+            let _ = back.send(stream);
+        }
+    }
+}
@@ -1,16 +1,24 @@
-use std::{net::SocketAddr, time::Duration};
+use std::{borrow::Cow, net::SocketAddr, ops::RangeInclusive, time::Duration};
 
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpStream,
+    net::{TcpListener, TcpStream},
+    sync::{Mutex, mpsc, oneshot},
     try_join,
 };
 
 use crate::{
     error::Error,
-    mapper::MappingHandler,
+    mapper::{
+        BorrowedStream, EventBus, FlapDamping, Ipv6ChangePolicy, MappingEvent, MappingHandler,
+        MappingInfo, Plan, PlannedConnection, Takeover,
+        damping::Damper,
+        port_scan,
+        takeover::{LendRequest, Running},
+    },
     net::connect_remote,
     net::{LocalAddr, RemoteAddr},
+    stun::BindingRequest,
 };
 
 /// Maintains a TCP connection and periodically discovers the public address via STUN.
@@ -19,8 +27,20 @@ pub struct TcpMapper {
     remote: RemoteAddr,
     stun: RemoteAddr,
     local: LocalAddr,
+    stun_local: Option<LocalAddr>,
     tick_interval: Duration,
+    stun_timeout: Duration,
+    flap_damping: Option<FlapDamping>,
+    port_fallback: Option<RangeInclusive<u16>>,
+    port_check: Option<RemoteAddr>,
     request: String,
+    verify_responder: bool,
+    ipv6_policy: Ipv6ChangePolicy,
+    lend_tx: mpsc::Sender<LendRequest>,
+    lend_rx: Mutex<mpsc::Receiver<LendRequest>>,
+    running: tokio::sync::watch::Sender<bool>,
+    #[cfg(feature = "tower")]
+    bound: tokio::sync::watch::Sender<Option<SocketAddr>>,
 }
 
 impl TcpMapper {
@@ -28,33 +48,141 @@ impl TcpMapper {
     /// Run the keepalive loop, calling `handler` whenever the public address changes.
     ///
     /// Returns only on unrecoverable error or after exhausting retries.
+    /// A mapper runs one session at a time: calling `run` while another
+    /// call is active fails at once with [`Error::AlreadyRunning`].
     pub async fn run<H: MappingHandler>(&self, handler: &mut H) -> Result<(), Error> {
-        let mut current_ip = None;
+        let mut lend_rx = self.lend_rx.try_lock().map_err(|_| Error::AlreadyRunning)?;
+        let _running = Running::start(&self.running);
+        let mut bus = EventBus::new(handler, self.ipv6_policy);
+        let mut damper = Damper::new(self.flap_damping);
         let mut retry_cnt = 0usize;
+        for local in std::iter::once(&self.local).chain(&self.stun_local) {
+            local.ensure_route().await.map_err(Error::Socket)?;
+        }
+        let local = match &self.port_fallback {
+            Some(ports) => Cow::Owned(
+                port_scan::select(
+                    &self.local,
+                    self.stun_local.as_ref(),
+                    ports,
+                    crate::net::Protocol::Tcp,
+                    &self.stun,
+                    self.port_check.as_ref(),
+                    &mut bus,
+                )
+                .await?,
+            ),
+            None => Cow::Borrowed(&self.local),
+        };
+        let stun_local = self.stun_local.as_ref().unwrap_or(&local);
 
         loop {
-            match TcpMapperReactor::new(&self.local, &self.remote, &self.stun).await {
-                Ok(mut actor) => {
+            bus.emit(MappingEvent::ProbeStarted);
+            match TcpMapperReactor::new(
+                &local,
+                stun_local,
+                &self.remote,
+                &self.stun,
+                self.stun_timeout,
+                self.verify_responder,
+            )
+            .await
+            {
+                Ok(actor) => {
                     retry_cnt = 0;
-                    let pub_addr = actor.pub_addr;
-                    if Some(pub_addr) != current_ip {
-                        current_ip = Some(pub_addr);
-                        handler.on_change(super::MappingInfo::new(pub_addr, actor.local_addr));
-                    }
+                    #[cfg(feature = "tower")]
+                    let _bound = super::connector::Bound::set(&self.bound, actor.local_addr);
+                    bus.probe_succeeded(MappingInfo::new(actor.pub_addr, actor.local_addr));
 
-                    let _ =
-                        keepalive(&mut actor.tcp_stream, &self.request, self.tick_interval).await;
+                    let _ = keepalive(
+                        actor.tcp_stream,
+                        &self.request,
+                        self.tick_interval,
+                        &mut lend_rx,
+                        actor.listener.as_ref(),
+                    )
+                    .await;
                 }
 
-                Err(e) if !e.is_recoverable() => return Err(e),
                 Err(e) => {
+                    bus.emit(MappingEvent::ProbeFailed(&e));
+                    if !e.is_recoverable() {
+                        return Err(e);
+                    }
                     retry_cnt += 1;
                     if retry_cnt >= Self::RETRY_LTD {
                         return Err(e);
                     }
                 }
             }
-            tokio::time::sleep(Duration::from_secs(5)).await;
+            bus.emit(MappingEvent::Reconnect);
+            damper.before_rebuild(&mut bus, &[&local, stun_local]).await;
+        }
+    }
+
+    /// Resolve DNS and describe what [`run`](Self::run) would create,
+    /// without sending any traffic.
+    pub async fn dry_run(&self) -> Result<Plan, Error> {
+        let (addr_ka, addr_st) = try_join!(
+            self.remote.resolve(&self.local),
+            self.stun
+                .resolve(self.stun_local.as_ref().unwrap_or(&self.local))
+        )?;
+        let check = port_scan::planned_check(
+            self.port_fallback.as_ref(),
+            self.port_check.as_ref(),
+            self.stun_local.as_ref().unwrap_or(&self.local),
+        )
+        .await?;
+        Ok(Plan {
+            protocol: "tcp",
+            local_addr: self.local.addr(),
+            port_fallback: self.port_fallback.clone(),
+            socket_options: self.local.socket_options(),
+            stun_local_addr: self.stun_local.as_ref().map(LocalAddr::addr),
+            stun_socket_options: self
+                .stun_local
+                .as_ref()
+                .map(LocalAddr::socket_options)
+                .unwrap_or_default(),
+            connections: vec![
+                PlannedConnection {
+                    purpose: "keepalive",
+                    remote: self.remote.to_string(),
+                    resolved: addr_ka,
+                },
+                PlannedConnection {
+                    purpose: "stun",
+                    remote: self.stun.to_string(),
+                    resolved: addr_st,
+                },
+            ]
+            .into_iter()
+            .chain(check)
+            .collect(),
+            interval: self.tick_interval,
+            check_per_tick: None,
+        })
+    }
+
+    /// Handle for temporarily borrowing the keepalive connection.
+    ///
+    /// See [`Takeover::borrow`].
+    pub fn takeover(&self) -> Takeover {
+        Takeover {
+            tx: self.lend_tx.clone(),
+            running: self.running.subscribe(),
+        }
+    }
+
+    /// Connector dialing outbound connections from this mapper's local port.
+    ///
+    /// See [`MappedConnector`](super::MappedConnector).
+    #[cfg(feature = "tower")]
+    pub fn connector(&self) -> super::MappedConnector {
+        super::MappedConnector {
+            local: self.local.clone(),
+            bound: self.bound.subscribe(),
         }
     }
 
@@ -69,24 +197,48 @@ impl TcpMapper {
                 addr.ip()
             ),
         };
+        let (lend_tx, lend_rx) = mpsc::channel(1);
         Self {
             remote,
             stun: builder.stun,
             local: builder.local,
+            stun_local: builder.stun_local,
             tick_interval: builder.interval,
+            stun_timeout: builder.stun_timeout,
+            flap_damping: builder.flap_damping,
+            port_fallback: builder.port_fallback,
+            port_check: builder.port_check,
             request,
+            verify_responder: builder.verify_responder,
+            ipv6_policy: builder.ipv6_policy,
+            lend_tx,
+            lend_rx: Mutex::new(lend_rx),
+            running: tokio::sync::watch::Sender::new(false),
+            #[cfg(feature = "tower")]
+            bound: tokio::sync::watch::Sender::new(None),
         }
     }
 }
 
+/// Verification requests answered concurrently by one keepalive session.
+const MAX_RESPONDERS: usize = 8;
+
 /// Send periodic HTTP HEAD requests to keep the TCP connection alive.
+///
+/// Pauses while the stream is lent out through a [`Takeover`]. Answers
+/// verification requests on `listener` in background tasks, at most
+/// [`MAX_RESPONDERS`] at once; further connections are dropped.
 async fn keepalive(
-    stream: &mut TcpStream,
+    mut stream: TcpStream,
     request: &str,
     interval: Duration,
+    lend_rx: &mut mpsc::Receiver<LendRequest>,
+    listener: Option<&TcpListener>,
 ) -> Result<(), std::io::Error> {
     let mut interval = tokio::time::interval(interval);
     let mut buf = [0u8; 8192];
+    // aborted when the session ends
+    let mut responders = tokio::task::JoinSet::new();
     loop {
         tokio::select! {
             _ = interval.tick() => {
@@ -99,25 +251,60 @@ async fn keepalive(
                 // ignore response body
                 Ok(_) => {}
                 Err(e) => return Err(e),
+            },
+
+            Some(reply) = lend_rx.recv() => {
+                let (back, returned) = oneshot::channel();
+                // a dropped requester hands the stream straight back
+                let _ = reply.send(BorrowedStream {
+                    stream: Some(stream),
+                    back: Some(back),
+                });
+                stream = match returned.await {
+                    Ok(stream) => stream,
+                    // borrower leaked the guard; treat as a lost session
+                    Err(_) => return Ok(()),
+                };
+                interval.reset();
+            }
+
+            Ok((conn, _)) = accept(listener) => {
+                if responders.len() < MAX_RESPONDERS {
+                    responders.spawn(crate::verify::respond_tcp(conn));
+                }
             }
+
+            Some(_) = responders.join_next() => {}
         }
     }
 }
 
+async fn accept(listener: Option<&TcpListener>) -> std::io::Result<(TcpStream, SocketAddr)> {
+    match listener {
+        Some(listener) => listener.accept().await,
+        None => std::future::pending().await,
+    }
+}
+
 struct TcpMapperReactor {
     local_addr: SocketAddr,
     tcp_stream: TcpStream,
     pub_addr: SocketAddr,
+    listener: Option<TcpListener>,
 }
 
 impl TcpMapperReactor {
     async fn new(
         local: &LocalAddr,
+        stun_local: &LocalAddr,
         ka_remote: &RemoteAddr,
         stun: &RemoteAddr,
+        stun_timeout: Duration,
+        verify_responder: bool,
     ) -> Result<Self, Error> {
         let socket_ka = local
             .socket(crate::net::Protocol::Tcp)
+            .await
             .map_err(Error::Socket)?;
 
         let local_addr = socket_ka
@@ -126,11 +313,12 @@ impl TcpMapperReactor {
             .as_socket()
             .unwrap();
 
-        let socket_st = local
-            .socket_from_addr(local_addr, crate::net::Protocol::Tcp)
+        let socket_st = stun_local
+            .socket_from_addr(stun_local.share_port(local_addr), crate::net::Protocol::Tcp)
+            .await
             .map_err(Error::Socket)?;
 
-        let (addr_ka, addr_st) = try_join!(ka_remote.socket_addr(), stun.socket_addr())?;
+        let (addr_ka, addr_st) = try_join!(ka_remote.resolve(local), stun.resolve(stun_local))?;
 
         // tcp connect
         let tcp_stream = connect_remote(socket_ka, addr_ka)
@@ -140,14 +328,26 @@ impl TcpMapperReactor {
         let stun_stream = connect_remote(socket_st, addr_st)
             .await
             .map_err(Error::Connection)?;
-        let pub_addr = crate::stun::tcp_socket_addr(stun_stream)
-            .await
-            .map_err(Error::from)?;
+        let pub_addr =
+            crate::stun::tcp_socket_addr(stun_stream, BindingRequest::default(), stun_timeout)
+                .await
+                .map_err(Error::from)?;
+
+        let listener = match verify_responder {
+            true => Some(
+                local
+                    .sole_tcp_listener(local_addr)
+                    .await
+                    .map_err(Error::Socket)?,
+            ),
+            false => None,
+        };
 
         Ok(Self {
             tcp_stream,
             local_addr,
             pub_addr,
+            listener,
         })
     }
 }
@@ -1,12 +1,15 @@
-use std::{net::SocketAddr, num::NonZeroUsize, time::Duration};
+use std::{borrow::Cow, net::SocketAddr, num::NonZeroUsize, ops::RangeInclusive, time::Duration};
 
 use tokio::net::UdpSocket;
 
 use crate::{
     error::Error,
-    mapper::MappingHandler,
+    mapper::{
+        EventBus, FlapDamping, Ipv6ChangePolicy, MappingEvent, MappingHandler, MappingInfo, Plan,
+        PlannedConnection, damping::Damper, pacing::Pacer, port_scan,
+    },
     net::{LocalAddr, RemoteAddr},
-    stun::StunUdpSocket,
+    stun::{BindingRequest, StunUdpSocket},
 };
 
 /// Sends UDP keepalive packets and periodically discovers the public address via STUN.
@@ -14,8 +17,15 @@ use crate::{
 pub struct UdpMapper {
     stun: RemoteAddr,
     local: LocalAddr,
+    stun_local: Option<LocalAddr>,
     interval: Duration,
+    stun_timeout: Duration,
+    flap_damping: Option<FlapDamping>,
+    port_fallback: Option<RangeInclusive<u16>>,
+    port_check: Option<RemoteAddr>,
     check_per_tick: NonZeroUsize,
+    verify_responder: bool,
+    ipv6_policy: Ipv6ChangePolicy,
 }
 
 impl UdpMapper {
@@ -23,35 +33,61 @@ impl UdpMapper {
 
     /// Run the keepalive loop, calling `handler` whenever the public address changes.
     pub async fn run<H: MappingHandler>(&self, handler: &mut H) -> Result<(), Error> {
-        let socket_st = self
-            .local
+        let mut bus = EventBus::new(handler, self.ipv6_policy);
+        for local in std::iter::once(&self.local).chain(&self.stun_local) {
+            local.ensure_route().await.map_err(Error::Socket)?;
+        }
+        let local = match &self.port_fallback {
+            Some(ports) => Cow::Owned(
+                port_scan::select(
+                    &self.local,
+                    self.stun_local.as_ref(),
+                    ports,
+                    crate::net::Protocol::Udp,
+                    &self.stun,
+                    self.port_check.as_ref(),
+                    &mut bus,
+                )
+                .await?,
+            ),
+            None => Cow::Borrowed(&self.local),
+        };
+        let socket_ka = local
             .socket(crate::net::Protocol::Udp)
+            .await
             .map_err(Error::Socket)?;
 
-        let local_addr = socket_st
+        let local_addr = socket_ka
             .local_addr()
             .map_err(Error::Socket)?
             .as_socket()
             .unwrap();
 
-        let socket_ka = self
-            .local
-            .udp_socket_from_addr(local_addr)
+        let stun_local = self.stun_local.as_ref().unwrap_or(&local);
+        let socket_st = stun_local
+            .udp_socket_from_addr(stun_local.share_port(local_addr))
+            .await
             .map_err(Error::Socket)?;
 
-        let socket_st = crate::net::udp_socket(socket_st).map_err(Error::Socket)?;
-        let mut current_ip = None;
+        let socket_ka = crate::net::udp_socket(socket_ka).map_err(Error::Socket)?;
+        let mut damper = Damper::new(self.flap_damping);
         let mut retry_cnt = 0usize;
 
         // TODO: refactor stupid code
         loop {
             // Phase 1: DNS + connect + initial STUN probe (errors → retry_cnt)
+            bus.emit(MappingEvent::ProbeStarted);
             let setup = async {
-                let stun_addr = self.stun.socket_addr().await?;
+                let stun_addr = self.stun.resolve(stun_local).await?;
                 let stun_socket = StunUdpSocket::new(&socket_st, stun_addr)
                     .await
                     .map_err(Error::Connection)?;
-                let pub_addr = crate::stun::udp_socket_addr(stun_socket).await?;
+                let pub_addr = crate::stun::udp_socket_addr(
+                    stun_socket,
+                    BindingRequest::default(),
+                    self.stun_timeout,
+                )
+                .await?;
                 Ok::<_, Error>((stun_addr, pub_addr))
             }
             .await;
@@ -59,10 +95,7 @@ impl UdpMapper {
             match setup {
                 Ok((stun_addr, pub_addr)) => {
                     retry_cnt = 0;
-                    if Some(pub_addr) != current_ip {
-                        current_ip = Some(pub_addr);
-                        handler.on_change(super::MappingInfo::new(pub_addr, local_addr));
-                    }
+                    bus.probe_succeeded(MappingInfo::new(pub_addr, local_addr));
 
                     let _ = self
                         .keepalive(
@@ -70,26 +103,31 @@ impl UdpMapper {
                             local_addr,
                             &socket_ka,
                             &stun_addr,
-                            &mut current_ip,
-                            handler,
+                            &mut bus,
                         )
                         .await;
                 }
-                Err(e) if matches!(e, Error::Socket(_)) => return Err(e),
                 Err(e) => {
+                    bus.emit(MappingEvent::ProbeFailed(&e));
+                    if matches!(e, Error::Socket(_)) {
+                        return Err(e);
+                    }
                     retry_cnt += 1;
                     if retry_cnt >= Self::RETRY_LTD {
                         return Err(e);
                     }
                 }
             }
-            tokio::time::sleep(Duration::from_secs(5)).await;
+            bus.emit(MappingEvent::Reconnect);
+            damper.before_rebuild(&mut bus, &[&local, stun_local]).await;
         }
     }
 
     /// Keepalive loop: periodic STUN re-probes and keepalive packets.
     ///
-    /// STUN re-probe failures are silently tolerated (mapping may still be valid).
+    /// STUN re-probe failures are silently tolerated (mapping may still be valid),
+    /// but sustained loss switches to faster probing (see [`Pacer`]) on top
+    /// of the keepalives.
     /// Any keepalive send failure exits immediately (like natmap).
     async fn keepalive<H: MappingHandler>(
         &self,
@@ -97,43 +135,137 @@ impl UdpMapper {
         local_addr: SocketAddr,
         socket_ka: &UdpSocket,
         stun_addr: &SocketAddr,
-        current_ip: &mut Option<SocketAddr>,
-        handler: &mut H,
+        bus: &mut EventBus<'_, H>,
     ) -> Result<(), Error> {
         let mut cnt = 1usize;
         let mut consecutive_failures = 0usize;
+        let mut pacer = Pacer::new();
         loop {
-            if cnt >= self.check_per_tick.get() {
-                // STUN re-probe: tolerate failures
-                if let Ok(pub_addr) = crate::stun::udp_socket_addr(socket_st).await {
-                    cnt = 1;
-                    consecutive_failures = 0;
-                    if current_ip != &Some(pub_addr) {
-                        *current_ip = Some(pub_addr);
-                        handler.on_change(super::MappingInfo::new(pub_addr, local_addr));
+            let probe = pacer.recovering() || cnt >= self.check_per_tick.get();
+            // keep sending keepalives while recovering: with `stun_local` the
+            // probes leave through another path and do not refresh this mapping
+            if !probe || pacer.recovering() {
+                if let Err(e) = socket_ka.send_to(b"nya", stun_addr).await {
+                    consecutive_failures += 1;
+                    if consecutive_failures >= Self::RETRY_LTD {
+                        return Err(Error::Keepalive(e));
                     }
                 } else {
-                    consecutive_failures += 1;
+                    consecutive_failures = 0;
+                    if !probe {
+                        cnt += 1;
+                    }
                 }
-            } else if let Err(e) = socket_ka.send_to(b"nya", stun_addr).await {
-                consecutive_failures += 1;
-                if consecutive_failures >= Self::RETRY_LTD {
-                    return Err(Error::Keepalive(e));
+            }
+            if probe {
+                // STUN re-probe: tolerate failures
+                bus.emit(MappingEvent::ProbeStarted);
+                let res = crate::stun::udp_socket_addr(
+                    socket_st,
+                    BindingRequest::default(),
+                    pacer.probe_timeout(self.interval).min(self.stun_timeout),
+                )
+                .await;
+                let pacing = pacer.record(res.is_ok());
+                match res {
+                    Ok(pub_addr) => {
+                        cnt = 1;
+                        consecutive_failures = 0;
+                        bus.probe_succeeded(MappingInfo::new(pub_addr, local_addr));
+                    }
+                    Err(e) => {
+                        consecutive_failures += 1;
+                        let e = Error::from(e);
+                        bus.emit(MappingEvent::ProbeFailed(&e));
+                    }
+                }
+                if let Some(recovering) = pacing {
+                    bus.emit(MappingEvent::LossRecovery(recovering));
+                }
+            }
+            self.idle(socket_ka, pacer.tick(self.interval)).await;
+        }
+    }
+
+    /// Wait for `duration`, answering verification requests on `socket_ka`
+    /// if enabled.
+    async fn idle(&self, socket_ka: &UdpSocket, duration: Duration) {
+        let sleep = tokio::time::sleep(duration);
+        if !self.verify_responder {
+            return sleep.await;
+        }
+        tokio::pin!(sleep);
+        let mut buf = [0u8; 64];
+        loop {
+            tokio::select! {
+                _ = &mut sleep => return,
+                Ok((len, peer)) = socket_ka.recv_from(&mut buf) => {
+                    if let Some(resp) = crate::verify::answer(&buf[..len]) {
+                        let _ = socket_ka.send_to(&resp, peer).await;
+                    }
                 }
-            } else {
-                cnt += 1;
-                consecutive_failures = 0;
             }
-            tokio::time::sleep(self.interval).await;
         }
     }
 
+    /// Resolve DNS and describe what [`run`](Self::run) would create,
+    /// without sending any traffic.
+    pub async fn dry_run(&self) -> Result<Plan, Error> {
+        let stun_addr = self
+            .stun
+            .resolve(self.stun_local.as_ref().unwrap_or(&self.local))
+            .await?;
+        let remote = self.stun.to_string();
+        let check = port_scan::planned_check(
+            self.port_fallback.as_ref(),
+            self.port_check.as_ref(),
+            self.stun_local.as_ref().unwrap_or(&self.local),
+        )
+        .await?;
+        Ok(Plan {
+            protocol: "udp",
+            local_addr: self.local.addr(),
+            port_fallback: self.port_fallback.clone(),
+            socket_options: self.local.socket_options(),
+            stun_local_addr: self.stun_local.as_ref().map(LocalAddr::addr),
+            stun_socket_options: self
+                .stun_local
+                .as_ref()
+                .map(LocalAddr::socket_options)
+                .unwrap_or_default(),
+            connections: vec![
+                PlannedConnection {
+                    purpose: "stun",
+                    remote: remote.clone(),
+                    resolved: stun_addr,
+                },
+                PlannedConnection {
+                    purpose: "keepalive",
+                    remote,
+                    resolved: stun_addr,
+                },
+            ]
+            .into_iter()
+            .chain(check)
+            .collect(),
+            interval: self.interval,
+            check_per_tick: Some(self.check_per_tick.get()),
+        })
+    }
+
     pub(super) fn new(builder: super::MapperBuilder<super::builder::UdpConfig>) -> Self {
         Self {
             stun: builder.stun,
             local: builder.local,
+            stun_local: builder.stun_local,
+            flap_damping: builder.flap_damping,
+            port_fallback: builder.port_fallback,
+            port_check: builder.port_check,
             interval: builder.interval,
+            stun_timeout: builder.stun_timeout,
             check_per_tick: builder.config.check_per_tick,
+            verify_responder: builder.verify_responder,
+            ipv6_policy: builder.ipv6_policy,
         }
     }
 }
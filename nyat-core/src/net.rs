@@ -1,35 +1,48 @@
 //! Network address types and low-level socket utilities.
+mod dns;
+#[cfg(unix)]
+mod iface;
+#[cfg(all(target_os = "linux", any(feature = "tcp", feature = "reuse_port")))]
+mod proc_net;
 #[cfg(all(feature = "reuse_port", target_os = "linux"))]
 mod reuse_port;
+#[cfg(target_os = "linux")]
+mod route;
+
+#[cfg(unix)]
+pub use iface::interface_addrs;
 
 use socket2::{Domain, Socket, Type};
 use std::net::SocketAddr;
-#[cfg(feature = "tcp")]
-use tokio::net::TcpStream;
-#[cfg(feature = "udp")]
 use tokio::net::UdpSocket;
+#[cfg(feature = "tcp")]
+use tokio::net::{TcpListener, TcpStream};
 use tokio::time::timeout;
 
 use crate::error::DnsError;
 
 const TIMEOUT_DURATION: std::time::Duration = std::time::Duration::from_secs(30);
 
-/// Local bind configuration: address, optional fwmark, and interface binding.
+/// Local bind configuration: address, optional fwmark, interface binding and
+/// DNS servers.
 ///
 /// Sockets created from this config have `SO_REUSEPORT` and `SO_REUSEADDR` set.
 ///
 /// # Platform support
 ///
-/// `with_fmark` and `with_iface` are Linux-only.
-#[derive(Debug)]
+/// `with_fmark`, `with_iface` and `with_route_table` are Linux-only.
+#[derive(Debug, Clone)]
 pub struct LocalAddr {
     local_addr: SocketAddr,
     #[cfg(target_os = "linux")]
     fmark: Option<u32>,
     #[cfg(target_os = "linux")]
     iface: Option<([u8; libc::IFNAMSIZ], u8)>,
+    #[cfg(target_os = "linux")]
+    route_table: Option<u32>,
     #[cfg(all(feature = "reuse_port", target_os = "linux"))]
-    reuse_port: bool,
+    reuse_port: Option<u32>,
+    dns: Vec<SocketAddr>,
 }
 
 impl LocalAddr {
@@ -41,8 +54,11 @@ impl LocalAddr {
             fmark: None,
             #[cfg(target_os = "linux")]
             iface: None,
+            #[cfg(target_os = "linux")]
+            route_table: None,
             #[cfg(all(feature = "reuse_port", target_os = "linux"))]
-            reuse_port: false,
+            reuse_port: None,
+            dns: Vec::new(),
         }
     }
 
@@ -53,6 +69,31 @@ impl LocalAddr {
         self
     }
 
+    /// Route sockets through routing table `table`.
+    ///
+    /// Sockets are marked with the [`with_fmark`](Self::with_fmark) mark,
+    /// or `table` itself if none is set (which may collide with marks
+    /// already in use; prefer an explicit one). A mapper's `run` installs
+    /// `ip rule add fwmark MARK lookup TABLE` over netlink for each address
+    /// family its sockets use; dry runs and probes only mark. An identical
+    /// existing rule is reused; rules are not removed on exit. Requires
+    /// `CAP_NET_ADMIN`.
+    #[cfg(target_os = "linux")]
+    #[must_use]
+    pub const fn with_route_table(mut self, table: u32) -> Self {
+        self.route_table = Some(table);
+        self
+    }
+
+    /// Mark applied to sockets: the fwmark, or the route table's id.
+    #[cfg(target_os = "linux")]
+    const fn mark(&self) -> Option<u32> {
+        match self.fmark {
+            Some(fmark) => Some(fmark),
+            None => self.route_table,
+        }
+    }
+
     /// Bind to a specific network interface (e.g. `b"eth0"`).
     ///
     /// # Panics
@@ -76,14 +117,94 @@ impl LocalAddr {
     /// Uses `pidfd_open(2)` + `pidfd_getfd(2)` to duplicate each matching socket
     /// from other processes and set `SO_REUSEPORT`. Requires `CAP_SYS_PTRACE`
     /// (or root) and Linux ≥ 5.6.
+    ///
+    /// If the owner re-creates its socket before our bind, the scan is
+    /// retried [`DEFAULT_REUSE_RETRIES`](Self::DEFAULT_REUSE_RETRIES) times;
+    /// see [`force_reuse_port_retries`](Self::force_reuse_port_retries).
     #[cfg(all(feature = "reuse_port", target_os = "linux"))]
     #[must_use]
-    pub const fn force_reuse_port(mut self) -> Self {
-        self.reuse_port = true;
+    pub const fn force_reuse_port(self) -> Self {
+        self.force_reuse_port_retries(Self::DEFAULT_REUSE_RETRIES)
+    }
+
+    /// Retries of [`force_reuse_port`](Self::force_reuse_port) after losing
+    /// the race to a re-created socket.
+    #[cfg(all(feature = "reuse_port", target_os = "linux"))]
+    pub const DEFAULT_REUSE_RETRIES: u32 = 3;
+
+    /// Like [`force_reuse_port`](Self::force_reuse_port), retrying the
+    /// scan-and-bind cycle up to `retries` times, with short growing delays,
+    /// when the bind still fails. The final error names the PIDs holding
+    /// the port.
+    #[cfg(all(feature = "reuse_port", target_os = "linux"))]
+    #[must_use]
+    pub const fn force_reuse_port_retries(mut self, retries: u32) -> Self {
+        self.reuse_port = Some(retries);
+        self
+    }
+
+    /// Resolve domain names through these DNS servers instead of the
+    /// system resolver.
+    ///
+    /// Queries are plain UDP, sent from sockets created by this config
+    /// (same interface and fwmark, and the same source address when the
+    /// server's family matches), so each uplink can use the resolver it can
+    /// reach. Servers are tried in order; an empty list restores the system
+    /// resolver.
+    #[must_use]
+    pub fn with_dns_servers(mut self, servers: impl IntoIterator<Item = SocketAddr>) -> Self {
+        self.dns = servers.into_iter().collect();
         self
     }
 
-    pub(crate) fn socket_from_addr(
+    /// Install the [`with_route_table`](Self::with_route_table) rule for
+    /// each address family these sockets use: the bind address's and the
+    /// DNS servers'. The netlink calls run on the blocking thread pool.
+    pub(crate) async fn ensure_route(&self) -> std::io::Result<()> {
+        #[cfg(target_os = "linux")]
+        if let (Some(table), Some(mark)) = (self.route_table, self.mark()) {
+            let mut families: Vec<bool> = std::iter::once(self.local_addr.is_ipv6())
+                .chain(self.dns.iter().map(SocketAddr::is_ipv6))
+                .collect();
+            families.sort_unstable();
+            families.dedup();
+            return tokio::task::spawn_blocking(move || {
+                families
+                    .into_iter()
+                    .try_for_each(|ipv6| route::ensure_rule(ipv6, mark, table))
+            })
+            .await
+            .map_err(std::io::Error::other)?;
+        }
+        Ok(())
+    }
+
+    /// Whether the bound interface is up, running and has a routable
+    /// address; `true` without an interface binding.
+    pub(crate) fn iface_up(&self) -> bool {
+        #[cfg(target_os = "linux")]
+        if let Some((buf, len)) = &self.iface {
+            return std::str::from_utf8(&buf[..*len as usize]).is_ok_and(iface::interface_running);
+        }
+        true
+    }
+
+    /// Resolve `domain` with this config's DNS servers, or the system
+    /// resolver if none are set.
+    pub(crate) async fn resolve(
+        &self,
+        domain: &str,
+        port: u16,
+        ver: Option<IpVer>,
+    ) -> Result<SocketAddr, DnsError> {
+        if self.dns.is_empty() {
+            resolve_dns((domain, port), ver).await
+        } else {
+            dns::lookup(&self.dns, self, domain, port, ver).await
+        }
+    }
+
+    pub(crate) async fn socket_from_addr(
         &self,
         socket_addr: SocketAddr,
         p: Protocol,
@@ -95,11 +216,11 @@ impl LocalAddr {
                 match p {
                     #[cfg(all(target_os = "linux", feature = "tcp"))]
                     Tcp => Type::STREAM.nonblocking(),
-                    #[cfg(all(target_os = "linux", feature = "udp"))]
+                    #[cfg(target_os = "linux")]
                     Udp => Type::DGRAM.nonblocking(),
                     #[cfg(all(not(target_os = "linux"), feature = "tcp"))]
                     Tcp => Type::STREAM,
-                    #[cfg(all(not(target_os = "linux"), feature = "udp"))]
+                    #[cfg(not(target_os = "linux"))]
                     Udp => Type::DGRAM,
                 }
             },
@@ -114,7 +235,7 @@ impl LocalAddr {
 
         #[cfg(target_os = "linux")]
         {
-            if let Some(fmark) = self.fmark {
+            if let Some(fmark) = self.mark() {
                 socket.set_mark(fmark)?;
             }
             if let Some((buf, len)) = &self.iface {
@@ -129,33 +250,107 @@ impl LocalAddr {
 
         #[cfg(all(feature = "reuse_port", target_os = "linux"))]
         if let Err(e) = socket.bind(sock_addr) {
-            if self.reuse_port && e.kind() == std::io::ErrorKind::AddrInUse {
-                reuse_port::force_reuse_port(socket_addr.port())?;
-                socket.bind(sock_addr)?;
-            } else {
-                return Err(e);
+            match self.reuse_port {
+                Some(retries) if e.kind() == std::io::ErrorKind::AddrInUse => {
+                    reuse_port::bind_forcing(&socket, socket_addr, retries).await?;
+                }
+                _ => return Err(e),
             }
         }
         Ok(socket)
     }
 
+    /// Options applied to every socket created from this config, for dry runs.
+    pub(crate) fn socket_options(&self) -> Vec<String> {
+        let mut opts = Vec::new();
+        #[cfg(unix)]
+        opts.push("SO_REUSEPORT".to_owned());
+        opts.push("SO_REUSEADDR".to_owned());
+        #[cfg(target_os = "linux")]
+        {
+            if let Some(fmark) = self.mark() {
+                opts.push(format!("SO_MARK={fmark}"));
+            }
+            if let (Some(table), Some(fmark)) = (self.route_table, self.mark()) {
+                opts.push(format!("ip rule fwmark {fmark} lookup {table}"));
+            }
+            if let Some((buf, len)) = &self.iface {
+                opts.push(format!(
+                    "SO_BINDTODEVICE={}",
+                    String::from_utf8_lossy(&buf[..*len as usize])
+                ));
+            }
+        }
+        #[cfg(all(feature = "reuse_port", target_os = "linux"))]
+        if let Some(retries) = self.reuse_port {
+            opts.push(format!(
+                "force SO_REUSEPORT on conflict ({retries} retries)"
+            ));
+        }
+        for server in &self.dns {
+            opts.push(format!("DNS={server}"));
+        }
+        opts
+    }
+
+    pub(crate) const fn addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Where a socket from this config binds to share the port of a socket
+    /// already bound at `bound`: the configured address, with port `0`
+    /// replaced by `bound`'s port.
+    pub(crate) fn share_port(&self, bound: SocketAddr) -> SocketAddr {
+        let mut addr = self.local_addr;
+        if addr.port() == 0 {
+            addr.set_port(bound.port());
+        }
+        addr
+    }
+
+    /// This config bound to `port` instead.
+    pub(crate) fn with_port(&self, port: u16) -> Self {
+        let mut local = self.clone();
+        local.local_addr.set_port(port);
+        local
+    }
+
     /// Create non-blocking & reuse port & reuse address, with no-exec flag
     /// and bind the local address
-    pub(crate) fn socket(&self, p: Protocol) -> std::io::Result<Socket> {
-        self.socket_from_addr(self.local_addr, p)
+    pub(crate) async fn socket(&self, p: Protocol) -> std::io::Result<Socket> {
+        self.socket_from_addr(self.local_addr, p).await
     }
 
     #[cfg(feature = "udp")]
-    pub(crate) fn udp_socket_from_addr(
+    pub(crate) async fn udp_socket_from_addr(
         &self,
         addr: SocketAddr,
     ) -> std::io::Result<tokio::net::UdpSocket> {
-        let socket = self.socket_from_addr(addr, Protocol::Udp)?;
+        let socket = self.socket_from_addr(addr, Protocol::Udp).await?;
         udp_socket(socket)
     }
+
+    /// Listen on `addr`, refusing (on Linux) if another socket already
+    /// listens on its port: with `SO_REUSEPORT` the kernel would spread that
+    /// service's connections across both listeners.
+    #[cfg(feature = "tcp")]
+    pub(crate) async fn sole_tcp_listener(&self, addr: SocketAddr) -> std::io::Result<TcpListener> {
+        #[cfg(target_os = "linux")]
+        if proc_net::tcp_listening(addr.port())? {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AddrInUse,
+                format!(
+                    "another socket listens on TCP port {}; the verify responder would take its connections",
+                    addr.port()
+                ),
+            ));
+        }
+        let socket = self.socket_from_addr(addr, Protocol::Tcp).await?;
+        socket.listen(128)?;
+        TcpListener::from_std(socket.into())
+    }
 }
 
-#[cfg(feature = "udp")]
 pub(crate) fn udp_socket(socket: Socket) -> std::io::Result<UdpSocket> {
     UdpSocket::from_std(socket.into())
 }
@@ -200,16 +395,24 @@ impl RemoteAddr {
         }
     }
 
-    /// get socket addr from remote addr
-    pub(crate) async fn socket_addr(&self) -> Result<SocketAddr, DnsError> {
-        use RemoteAddrKind::*;
+    /// Resolve with `local`'s DNS servers, see [`LocalAddr::with_dns_servers`].
+    pub(crate) async fn resolve(&self, local: &LocalAddr) -> Result<SocketAddr, DnsError> {
         match &self.kind {
-            Host {
+            RemoteAddrKind::Host {
                 domain,
                 port,
                 ver_preference,
-            } => resolve_dns((domain.as_ref(), *port), *ver_preference).await,
-            Resolved(addr) => Ok(*addr),
+            } => local.resolve(domain, *port, *ver_preference).await,
+            RemoteAddrKind::Resolved(addr) => Ok(*addr),
+        }
+    }
+}
+
+impl std::fmt::Display for RemoteAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            RemoteAddrKind::Resolved(addr) => write!(f, "{addr}"),
+            RemoteAddrKind::Host { domain, port, .. } => write!(f, "{domain}:{port}"),
         }
     }
 }
@@ -235,7 +438,6 @@ pub enum IpVer {
 pub(crate) enum Protocol {
     #[cfg(feature = "tcp")]
     Tcp,
-    #[cfg(feature = "udp")]
     Udp,
 }
 
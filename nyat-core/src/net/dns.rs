@@ -0,0 +1,194 @@
+//! Minimal DNS stub resolver for per-uplink servers.
+//!
+//! Only what a mapper needs: one A or AAAA question over UDP, recursion
+//! desired, answers taken from the answer section. Queries are sent from a
+//! socket created by the task's [`LocalAddr`], so they leave through the
+//! same interface / fwmark as the mapping itself.
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use tokio::time::timeout;
+
+use super::{IpVer, LocalAddr, Protocol};
+use crate::error::DnsError;
+
+const QTYPE_A: u16 = 1;
+const QTYPE_AAAA: u16 = 28;
+const RCODE_NXDOMAIN: u8 = 3;
+const HEADER_SIZE: usize = 12;
+const ATTEMPT_TIMEOUT: Duration = Duration::from_secs(3);
+const ATTEMPTS: usize = 2;
+
+/// Resolve `domain` through `servers`, tried in order.
+///
+/// `None` preference asks for A records first, then AAAA.
+pub(super) async fn lookup(
+    servers: &[SocketAddr],
+    local: &LocalAddr,
+    domain: &str,
+    port: u16,
+    ver: Option<IpVer>,
+) -> Result<SocketAddr, DnsError> {
+    if let Ok(ip) = domain.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, port));
+    }
+    let qtypes: &[u16] = match ver {
+        Some(IpVer::V4) => &[QTYPE_A],
+        Some(IpVer::V6) => &[QTYPE_AAAA],
+        None => &[QTYPE_A, QTYPE_AAAA],
+    };
+
+    let mut last_err = None;
+    for &server in servers {
+        let mut nxdomain = false;
+        for &qtype in qtypes {
+            match query(server, local, domain, qtype).await {
+                Ok(Answer::Addrs(ips)) if !ips.is_empty() => {
+                    return Ok(SocketAddr::new(ips[0], port));
+                }
+                Ok(Answer::Addrs(_)) => {}
+                Ok(Answer::Rcode(RCODE_NXDOMAIN)) => nxdomain = true,
+                Ok(Answer::Rcode(rcode)) => {
+                    last_err = Some(io::Error::other(format!(
+                        "DNS server {server} answered with rcode {rcode}"
+                    )));
+                }
+                Err(e) => last_err = Some(e),
+            }
+        }
+        // an authoritative "does not exist" is not worth asking elsewhere
+        if nxdomain {
+            return Err(DnsError::AddrNotFound);
+        }
+    }
+    Err(last_err.map_or(DnsError::AddrNotFound, DnsError::Resolve))
+}
+
+enum Answer {
+    Addrs(Vec<IpAddr>),
+    Rcode(u8),
+}
+
+async fn query(
+    server: SocketAddr,
+    local: &LocalAddr,
+    domain: &str,
+    qtype: u16,
+) -> io::Result<Answer> {
+    // keep the task's source address when the families match, so that
+    // source-based routing picks the same uplink
+    let bind_ip = match (local.addr().ip(), server.ip()) {
+        (ip @ IpAddr::V4(_), IpAddr::V4(_)) | (ip @ IpAddr::V6(_), IpAddr::V6(_)) => ip,
+        (_, IpAddr::V4(_)) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        (_, IpAddr::V6(_)) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    let socket = local
+        .socket_from_addr(SocketAddr::new(bind_ip, 0), Protocol::Udp)
+        .await?;
+    let socket = super::udp_socket(socket)?;
+    socket.connect(server).await?;
+
+    let tx_id = crate::stun::random_tx_id();
+    let id = u16::from_be_bytes([tx_id[0], tx_id[1]]);
+    let request = build_query(id, domain, qtype)?;
+
+    let mut buf = [0u8; 1500];
+    for _ in 0..ATTEMPTS {
+        socket.send(&request).await?;
+        let wait = async {
+            loop {
+                let len = socket.recv(&mut buf).await?;
+                if let Some(answer) = parse_response(&buf[..len], id, qtype) {
+                    return Ok::<_, io::Error>(answer);
+                }
+            }
+        };
+        if let Ok(res) = timeout(ATTEMPT_TIMEOUT, wait).await {
+            return res;
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::TimedOut,
+        format!("DNS server {server} did not answer"),
+    ))
+}
+
+fn build_query(id: u16, domain: &str, qtype: u16) -> io::Result<Vec<u8>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "invalid domain name");
+    let domain = domain.strip_suffix('.').unwrap_or(domain);
+    if domain.is_empty() || domain.len() > 253 {
+        return Err(invalid());
+    }
+
+    let mut msg = Vec::with_capacity(HEADER_SIZE + domain.len() + 6);
+    msg.extend_from_slice(&id.to_be_bytes());
+    msg.extend_from_slice(&0x0100u16.to_be_bytes()); // RD
+    msg.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]); // QDCOUNT = 1
+    for label in domain.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(invalid());
+        }
+        msg.push(label.len() as u8);
+        msg.extend_from_slice(label.as_bytes());
+    }
+    msg.push(0);
+    msg.extend_from_slice(&qtype.to_be_bytes());
+    msg.extend_from_slice(&1u16.to_be_bytes()); // IN
+    Ok(msg)
+}
+
+/// `None` for anything that is not a well-formed response to `id`.
+fn parse_response(msg: &[u8], id: u16, qtype: u16) -> Option<Answer> {
+    if msg.len() < HEADER_SIZE || msg[0..2] != id.to_be_bytes() || msg[2] & 0x80 == 0 {
+        return None;
+    }
+    let rcode = msg[3] & 0x0f;
+    if rcode != 0 {
+        return Some(Answer::Rcode(rcode));
+    }
+    let qdcount = u16::from_be_bytes([msg[4], msg[5]]);
+    let ancount = u16::from_be_bytes([msg[6], msg[7]]);
+
+    let mut pos = HEADER_SIZE;
+    for _ in 0..qdcount {
+        pos = skip_name(msg, pos)? + 4;
+    }
+
+    let mut addrs = Vec::new();
+    for _ in 0..ancount {
+        pos = skip_name(msg, pos)?;
+        let rr = msg.get(pos..pos + 10)?;
+        let rtype = u16::from_be_bytes([rr[0], rr[1]]);
+        let rdlen = u16::from_be_bytes([rr[8], rr[9]]) as usize;
+        let rdata = msg.get(pos + 10..pos + 10 + rdlen)?;
+        pos += 10 + rdlen;
+
+        // CNAMEs are followed by the recursive server in the same section
+        match (rtype, rdata.len()) {
+            (QTYPE_A, 4) if qtype == QTYPE_A => {
+                let octets: [u8; 4] = rdata.try_into().ok()?;
+                addrs.push(IpAddr::from(octets));
+            }
+            (QTYPE_AAAA, 16) if qtype == QTYPE_AAAA => {
+                let octets: [u8; 16] = rdata.try_into().ok()?;
+                addrs.push(IpAddr::from(octets));
+            }
+            _ => {}
+        }
+    }
+    Some(Answer::Addrs(addrs))
+}
+
+/// Position after the (possibly compressed) name at `pos`.
+fn skip_name(msg: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *msg.get(pos)?;
+        match len {
+            0 => return Some(pos + 1),
+            l if l & 0xc0 == 0xc0 => return Some(pos + 2),
+            l => pos += 1 + l as usize,
+        }
+    }
+}
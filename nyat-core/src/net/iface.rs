@@ -0,0 +1,100 @@
+//! Interface address enumeration via `getifaddrs(3)`.
+
+use std::ffi::CStr;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// List the addresses on interface `name` that can reach beyond the link.
+///
+/// Loopback, link-local, unspecified and IPv6 unique-local (`fc00::/7`)
+/// addresses are skipped. Private IPv4 addresses are kept, since a WAN
+/// behind carrier-grade NAT usually has one.
+pub fn interface_addrs(name: &str) -> io::Result<Vec<IpAddr>> {
+    let mut head: *mut libc::ifaddrs = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut head) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut addrs = Vec::new();
+    let mut cur = head;
+    while !cur.is_null() {
+        let ifa = unsafe { &*cur };
+        cur = ifa.ifa_next;
+
+        if ifa.ifa_addr.is_null() || ifa.ifa_name.is_null() {
+            continue;
+        }
+        if unsafe { CStr::from_ptr(ifa.ifa_name) }.to_bytes() != name.as_bytes() {
+            continue;
+        }
+
+        let ip = match i32::from(unsafe { (*ifa.ifa_addr).sa_family }) {
+            libc::AF_INET => {
+                let sin = unsafe { &*(ifa.ifa_addr as *const libc::sockaddr_in) };
+                IpAddr::V4(Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr)))
+            }
+            libc::AF_INET6 => {
+                let sin6 = unsafe { &*(ifa.ifa_addr as *const libc::sockaddr_in6) };
+                IpAddr::V6(Ipv6Addr::from(sin6.sin6_addr.s6_addr))
+            }
+            _ => continue,
+        };
+
+        if is_routable(&ip) && !addrs.contains(&ip) {
+            addrs.push(ip);
+        }
+    }
+
+    unsafe { libc::freeifaddrs(head) };
+
+    if addrs.is_empty() && !interface_exists(name) {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no such interface: {name}"),
+        ));
+    }
+    Ok(addrs)
+}
+
+/// Whether `name` is up and running (has carrier) with a routable address.
+#[cfg(target_os = "linux")]
+pub(crate) fn interface_running(name: &str) -> bool {
+    let mut head: *mut libc::ifaddrs = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut head) } != 0 {
+        return false;
+    }
+
+    let wanted = (libc::IFF_UP | libc::IFF_RUNNING) as libc::c_uint;
+    let mut running = false;
+    let mut cur = head;
+    while !cur.is_null() {
+        let ifa = unsafe { &*cur };
+        cur = ifa.ifa_next;
+        if !ifa.ifa_name.is_null()
+            && unsafe { CStr::from_ptr(ifa.ifa_name) }.to_bytes() == name.as_bytes()
+            && ifa.ifa_flags & wanted == wanted
+        {
+            running = true;
+            break;
+        }
+    }
+    unsafe { libc::freeifaddrs(head) };
+
+    running && interface_addrs(name).is_ok_and(|addrs| !addrs.is_empty())
+}
+
+fn interface_exists(name: &str) -> bool {
+    std::ffi::CString::new(name).is_ok_and(|c| unsafe { libc::if_nametoindex(c.as_ptr()) } != 0)
+}
+
+fn is_routable(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => !(v4.is_loopback() || v4.is_link_local() || v4.is_unspecified()),
+        IpAddr::V6(v6) => {
+            !(v6.is_loopback()
+                || v6.is_unspecified()
+                || v6.is_unicast_link_local()
+                || v6.is_unique_local())
+        }
+    }
+}
@@ -0,0 +1,82 @@
+//! Sockets listed in `/proc/net/{tcp,udp}{,6}`.
+
+use std::fs;
+use std::io;
+
+/// Socket tables and whether each lists TCP sockets.
+pub(super) const PROC_SOURCES: [(&str, bool); 4] = [
+    ("/proc/net/tcp", true),
+    ("/proc/net/tcp6", true),
+    ("/proc/net/udp", false),
+    ("/proc/net/udp6", false),
+];
+
+/// TCP_LISTEN state in `/proc/net/tcp`.
+const TCP_LISTEN: u32 = 0x0A;
+
+/// Whether any TCP socket in this network namespace listens on `port`.
+#[cfg(feature = "tcp")]
+pub(super) fn tcp_listening(port: u16) -> io::Result<bool> {
+    for (path, _) in PROC_SOURCES.iter().filter(|(_, is_tcp)| *is_tcp) {
+        if !find_inodes(path, port, true)?.is_empty() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Parse `/proc/net/{tcp,udp}{,6}` for all sockets matching `port`.
+///
+/// For TCP only LISTEN sockets are matched; for UDP any bound socket qualifies.
+pub(super) fn find_inodes(path: &str, port: u16, is_tcp: bool) -> io::Result<Vec<u64>> {
+    let content = match fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut inodes = Vec::new();
+
+    // Fields (whitespace-separated):
+    //  [0] sl  [1] local_addr:port  [2] rem_addr:port  [3] state
+    //  [4] tx:rx  [5] tr:tm  [6] retrnsmt  [7] uid  [8] timeout  [9] inode
+    for line in content.lines().skip(1) {
+        let mut fields = line.split_whitespace();
+
+        let _sl = fields.next();
+        let local = match fields.next() {
+            Some(s) => s,
+            None => continue,
+        };
+        let _remote = fields.next();
+        let state = match fields.next() {
+            Some(s) => s,
+            None => continue,
+        };
+
+        let local_port = local
+            .rsplit(':')
+            .next()
+            .and_then(|s| u16::from_str_radix(s, 16).ok())
+            .unwrap_or(0);
+        if local_port != port {
+            continue;
+        }
+
+        if is_tcp {
+            let st = u32::from_str_radix(state, 16).unwrap_or(0);
+            if st != TCP_LISTEN {
+                continue;
+            }
+        }
+
+        // nth(5) skips fields [4]–[8] and returns [9] (inode)
+        if let Some(inode) = fields.nth(5).and_then(|s| s.parse::<u64>().ok())
+            && inode > 0
+        {
+            inodes.push(inode);
+        }
+    }
+
+    Ok(inodes)
+}
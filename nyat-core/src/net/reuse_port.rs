@@ -8,84 +8,82 @@
 
 use std::fs;
 use std::io;
+use std::net::SocketAddr;
 use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::time::Duration;
 
-const PROC_SOURCES: [(&str, bool); 4] = [
-    ("/proc/net/tcp", true),
-    ("/proc/net/tcp6", true),
-    ("/proc/net/udp", false),
-    ("/proc/net/udp6", false),
-];
+use socket2::Socket;
 
-/// TCP_LISTEN state in `/proc/net/tcp`.
-const TCP_LISTEN: u32 = 0x0A;
+use super::proc_net::{PROC_SOURCES, find_inodes};
 
-/// Force `SO_REUSEPORT` on all existing sockets bound to `port`.
-pub(crate) fn force_reuse_port(port: u16) -> io::Result<()> {
-    for &(path, is_tcp) in &PROC_SOURCES {
-        for inode in find_inodes(path, port, is_tcp)? {
-            if let Some((pid, fd)) = find_pid_fd(inode)? {
-                set_reuse_port(pid, fd)?;
-            }
-        }
-    }
-    Ok(())
-}
+/// Delay before the first retry; each further retry waits one more step.
+const RETRY_DELAY: Duration = Duration::from_millis(50);
 
-/// Parse `/proc/net/{tcp,udp}{,6}` for all sockets matching `port`.
+/// Bind `socket` to `addr` after forcing `SO_REUSEPORT` on the sockets
+/// holding the port.
 ///
-/// For TCP only LISTEN sockets are matched; for UDP any bound socket qualifies.
-fn find_inodes(path: &str, port: u16, is_tcp: bool) -> io::Result<Vec<u64>> {
-    let content = match fs::read_to_string(path) {
-        Ok(c) => c,
-        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
-        Err(e) => return Err(e),
-    };
-
-    let mut inodes = Vec::new();
-
-    // Fields (whitespace-separated):
-    //  [0] sl  [1] local_addr:port  [2] rem_addr:port  [3] state
-    //  [4] tx:rx  [5] tr:tm  [6] retrnsmt  [7] uid  [8] timeout  [9] inode
-    for line in content.lines().skip(1) {
-        let mut fields = line.split_whitespace();
-
-        let _sl = fields.next();
-        let local = match fields.next() {
-            Some(s) => s,
-            None => continue,
-        };
-        let _remote = fields.next();
-        let state = match fields.next() {
-            Some(s) => s,
-            None => continue,
-        };
-
-        let local_port = local
-            .rsplit(':')
-            .next()
-            .and_then(|s| u16::from_str_radix(s, 16).ok())
-            .unwrap_or(0);
-        if local_port != port {
-            continue;
+/// The owner may re-create its socket between our scan and our bind, so a
+/// bind that still fails with `EADDRINUSE` rescans and retries up to
+/// `retries` times, sleeping 50 ms, 100 ms, ... in between. If every attempt
+/// loses the race, the error names the PIDs holding the port.
+pub(crate) async fn bind_forcing(
+    socket: &Socket,
+    addr: SocketAddr,
+    retries: u32,
+) -> io::Result<()> {
+    for attempt in 0..=retries {
+        if attempt > 0 {
+            tokio::time::sleep(RETRY_DELAY * attempt).await;
+        }
+        for (pid, fd) in holders(addr.port())? {
+            set_reuse_port(pid, fd)?;
         }
+        match socket.bind(&addr.into()) {
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse => {}
+            res => return res,
+        }
+    }
 
-        if is_tcp {
-            let st = u32::from_str_radix(state, 16).unwrap_or(0);
-            if st != TCP_LISTEN {
-                continue;
-            }
+    let mut pids: Vec<u32> = holders(addr.port())?
+        .into_iter()
+        .map(|(pid, _)| pid)
+        .collect();
+    pids.sort_unstable();
+    pids.dedup();
+    // the holder may live in another network namespace, or have closed
+    // its socket since the last bind
+    let holders = match pids.as_slice() {
+        [] => "no holder found in /proc".to_owned(),
+        pids => {
+            let pids = pids
+                .iter()
+                .map(u32::to_string)
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!("held by PID {pids}")
         }
+    };
+    Err(io::Error::new(
+        io::ErrorKind::AddrInUse,
+        format!(
+            "port {} still in use after {} forced SO_REUSEPORT attempt(s), {holders}",
+            addr.port(),
+            retries + 1,
+        ),
+    ))
+}
 
-        // nth(5) skips fields [4]–[8] and returns [9] (inode)
-        if let Some(inode) = fields.nth(5).and_then(|s| s.parse::<u64>().ok())
-            && inode > 0
-        {
-            inodes.push(inode);
+/// `(pid, fd)` of every socket bound to `port`.
+fn holders(port: u16) -> io::Result<Vec<(u32, RawFd)>> {
+    let mut holders = Vec::new();
+    for &(path, is_tcp) in &PROC_SOURCES {
+        for inode in find_inodes(path, port, is_tcp)? {
+            if let Some(holder) = find_pid_fd(inode)? {
+                holders.push(holder);
+            }
         }
     }
-
-    Ok(inodes)
+    Ok(holders)
 }
 
 /// Scan `/proc/<pid>/fd/<fd>` symlinks for one pointing to `socket:[<inode>]`.
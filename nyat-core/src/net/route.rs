@@ -0,0 +1,194 @@
+//! Policy routing rules via rtnetlink.
+//!
+//! Installs the equivalent of `ip rule add fwmark MARK lookup TABLE` so that
+//! marked sockets are routed through a given table. Requires
+//! `CAP_NET_ADMIN`.
+
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::{Mutex, PoisonError};
+
+const NLMSG_HDRLEN: usize = 16;
+const FIB_RULE_HDRLEN: usize = 12;
+const FR_ACT_TO_TBL: u8 = 1;
+const FRA_FWMARK: u16 = 10;
+const FRA_TABLE: u16 = 15;
+
+/// `(family, mark, table)` of rules already installed by this process.
+static INSTALLED: Mutex<Vec<(u8, u32, u32)>> = Mutex::new(Vec::new());
+
+/// Make sure `fwmark mark lookup table` exists for IPv4 or IPv6.
+///
+/// An existing rule with the same mark and table, e.g. from an earlier run,
+/// is reused; otherwise one is added at the kernel's default priority. Each
+/// rule is checked once per process and left in place on exit.
+pub(crate) fn ensure_rule(ipv6: bool, mark: u32, table: u32) -> io::Result<()> {
+    let family = if ipv6 { libc::AF_INET6 } else { libc::AF_INET } as u8;
+    let mut installed = INSTALLED.lock().unwrap_or_else(PoisonError::into_inner);
+    if installed.contains(&(family, mark, table)) {
+        return Ok(());
+    }
+    let res = netlink_socket().and_then(|fd| {
+        if rule_exists(&fd, family, mark, table)? {
+            Ok(())
+        } else {
+            add_rule(&fd, family, mark, table)
+        }
+    });
+    res.map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("failed to add `ip rule fwmark {mark} lookup {table}`: {e}"),
+        )
+    })?;
+    installed.push((family, mark, table));
+    Ok(())
+}
+
+fn netlink_socket() -> io::Result<OwnedFd> {
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+            libc::NETLINK_ROUTE,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+/// Start a message: `struct nlmsghdr` (length patched by [`send`]) and
+/// `struct fib_rule_hdr`.
+fn rule_msg(msg_type: u16, flags: libc::c_int, seq: u32, family: u8, table: u8) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(NLMSG_HDRLEN + FIB_RULE_HDRLEN + 16);
+    msg.extend_from_slice(&[0; 4]);
+    msg.extend_from_slice(&msg_type.to_ne_bytes());
+    msg.extend_from_slice(&(flags as u16).to_ne_bytes());
+    msg.extend_from_slice(&seq.to_ne_bytes());
+    msg.extend_from_slice(&0u32.to_ne_bytes()); // nlmsg_pid
+    msg.extend_from_slice(&[family, 0, 0, 0, table, 0, 0, FR_ACT_TO_TBL]);
+    msg.extend_from_slice(&0u32.to_ne_bytes());
+    msg
+}
+
+fn send(fd: &OwnedFd, mut msg: Vec<u8>) -> io::Result<()> {
+    let len = msg.len() as u32;
+    msg[..4].copy_from_slice(&len.to_ne_bytes());
+    let sent = unsafe { libc::send(fd.as_raw_fd(), msg.as_ptr().cast(), msg.len(), 0) };
+    if sent < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn recv<'a>(fd: &OwnedFd, buf: &'a mut [u8]) -> io::Result<&'a [u8]> {
+    let len = unsafe { libc::recv(fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len(), 0) };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(&buf[..len as usize])
+}
+
+/// `(type, payload)` of each netlink message in `data`.
+fn messages(mut data: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    std::iter::from_fn(move || {
+        let len = u32::from_ne_bytes(data.get(..4)?.try_into().ok()?) as usize;
+        if len < NLMSG_HDRLEN || len > data.len() {
+            return None;
+        }
+        let msg_type = u16::from_ne_bytes([data[4], data[5]]);
+        let payload = &data[NLMSG_HDRLEN..len];
+        data = data.get(len.next_multiple_of(4)..).unwrap_or_default();
+        Some((msg_type, payload))
+    })
+}
+
+/// Errno of an `NLMSG_ERROR` payload; `0` for an ACK.
+fn errno(payload: &[u8]) -> io::Result<i32> {
+    let errno = payload
+        .get(..4)
+        .ok_or_else(|| io::Error::other("truncated netlink error"))?;
+    Ok(-i32::from_ne_bytes(errno.try_into().unwrap()))
+}
+
+/// Whether a `fwmark mark lookup table` rule is already installed.
+fn rule_exists(fd: &OwnedFd, family: u8, mark: u32, table: u32) -> io::Result<bool> {
+    let flags = libc::NLM_F_REQUEST | libc::NLM_F_DUMP;
+    send(fd, rule_msg(libc::RTM_GETRULE, flags, 1, family, 0))?;
+
+    let mut found = false;
+    let mut buf = vec![0u8; 32 * 1024];
+    loop {
+        for (msg_type, payload) in messages(recv(fd, &mut buf)?) {
+            match libc::c_int::from(msg_type) {
+                libc::NLMSG_DONE => return Ok(found),
+                libc::NLMSG_ERROR => {
+                    return Err(io::Error::from_raw_os_error(errno(payload)?));
+                }
+                _ if msg_type == libc::RTM_NEWRULE => {
+                    found |= rule_matches(payload, mark, table);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Whether a dumped rule is a plain `fwmark mark lookup table`.
+fn rule_matches(payload: &[u8], mark: u32, table: u32) -> bool {
+    let Some(hdr) = payload.get(..FIB_RULE_HDRLEN) else {
+        return false;
+    };
+    if hdr[7] != FR_ACT_TO_TBL {
+        return false;
+    }
+    let (mut rule_mark, mut rule_table) = (None, u32::from(hdr[4]));
+    let mut attrs = &payload[FIB_RULE_HDRLEN..];
+    while let Some(attr) = attrs.get(..4) {
+        let len = u16::from_ne_bytes([attr[0], attr[1]]) as usize;
+        // a length below the header would never advance
+        if len < 4 {
+            break;
+        }
+        let Some(value) = attrs.get(4..len) else {
+            break;
+        };
+        let value = value.try_into().map(u32::from_ne_bytes).ok();
+        match u16::from_ne_bytes([attr[2], attr[3]]) {
+            FRA_FWMARK => rule_mark = value,
+            FRA_TABLE => rule_table = value.unwrap_or(rule_table),
+            _ => {}
+        }
+        attrs = attrs.get(len.next_multiple_of(4)..).unwrap_or_default();
+    }
+    rule_mark == Some(mark) && rule_table == table
+}
+
+fn add_rule(fd: &OwnedFd, family: u8, mark: u32, table: u32) -> io::Result<()> {
+    let flags = libc::NLM_F_REQUEST | libc::NLM_F_ACK | libc::NLM_F_CREATE;
+    // tables beyond 255 only fit in FRA_TABLE
+    let hdr_table = u8::try_from(table).unwrap_or(0);
+    let mut msg = rule_msg(libc::RTM_NEWRULE, flags, 2, family, hdr_table);
+    push_attr_u32(&mut msg, FRA_FWMARK, mark);
+    push_attr_u32(&mut msg, FRA_TABLE, table);
+    send(fd, msg)?;
+
+    let mut buf = [0u8; 1024];
+    match messages(recv(fd, &mut buf)?).next() {
+        Some((msg_type, payload)) if libc::c_int::from(msg_type) == libc::NLMSG_ERROR => {
+            match errno(payload)? {
+                0 => Ok(()),
+                e => Err(io::Error::from_raw_os_error(e)),
+            }
+        }
+        _ => Err(io::Error::other("unexpected netlink reply")),
+    }
+}
+
+fn push_attr_u32(msg: &mut Vec<u8>, attr_type: u16, value: u32) {
+    msg.extend_from_slice(&8u16.to_ne_bytes());
+    msg.extend_from_slice(&attr_type.to_ne_bytes());
+    msg.extend_from_slice(&value.to_ne_bytes());
+}
@@ -0,0 +1,163 @@
+//! One-shot STUN probes.
+//!
+//! Unlike a [`Mapper`](crate::mapper), a probe does not keep the mapping
+//! alive: it binds once, asks the STUN server for the public address and
+//! returns. Useful for discovery, e.g. probing from every address of an
+//! interface to pick which one to publish.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use nyat_core::net::{LocalAddr, RemoteAddr};
+//!
+//! # async fn example() -> Result<(), nyat_core::Error> {
+//! let stun = RemoteAddr::from_host("stun.l.google.com", 19302, None);
+//! for ip in nyat_core::net::interface_addrs("eth0").map_err(nyat_core::Error::Socket)? {
+//!     let local = LocalAddr::new((ip, 0).into());
+//!     let info = nyat_core::probe::udp(&local, &stun).await?;
+//!     println!("{} -> {}", info.local_addr, info.pub_addr);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::error::Error;
+use crate::mapper::MappingInfo;
+use crate::net::{LocalAddr, Protocol, RemoteAddr};
+use crate::stun::BindingRequest;
+
+/// Extra Binding Request attributes for diagnostic probes (RFC 5780).
+///
+/// # Examples
+///
+/// ```no_run
+/// use nyat_core::net::{LocalAddr, RemoteAddr};
+/// use nyat_core::probe::ProbeOptions;
+///
+/// # async fn example() -> Result<(), nyat_core::Error> {
+/// let local = LocalAddr::new("0.0.0.0:4070".parse().unwrap());
+/// let stun = RemoteAddr::from_host("stun.example.com", 3478, None);
+/// // ~1400-byte request
+/// let opts = ProbeOptions::new().padding(1352);
+/// let info = nyat_core::probe::udp_with(&local, &stun, opts).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProbeOptions {
+    attrs: BindingRequest,
+}
+
+impl ProbeOptions {
+    /// Largest accepted PADDING length.
+    pub const MAX_PADDING: u16 = BindingRequest::MAX_PADDING;
+
+    /// No extra attributes: a plain Binding Request.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            attrs: BindingRequest::new(),
+        }
+    }
+
+    /// Add a PADDING attribute of `len` bytes, growing the request to
+    /// 24 + `len` bytes (rounded up to a multiple of 4), plus 8 with
+    /// RESPONSE-PORT. Useful to test whether large or fragmented probes
+    /// survive the path. Clamped to [`MAX_PADDING`](Self::MAX_PADDING) so
+    /// the request fits in a UDP datagram.
+    #[must_use]
+    pub const fn padding(mut self, len: u16) -> Self {
+        self.attrs.padding = if len > Self::MAX_PADDING {
+            Self::MAX_PADDING
+        } else {
+            len
+        };
+        self
+    }
+
+    /// Add a RESPONSE-PORT attribute asking the server to send its reply
+    /// to `port` on the public address instead of the mapped port.
+    ///
+    /// The reply is still awaited on the probe socket, so the probe only
+    /// succeeds if `port` leads back to it — e.g. the public port of an
+    /// existing mapping of the same local port (RFC 5780 binding lifetime
+    /// test). A timeout means the NAT dropped or misrouted the reply;
+    /// [`Error::StunErrorResponse`] with `420` means the server does not
+    /// support the attribute.
+    #[must_use]
+    pub const fn response_port(mut self, port: u16) -> Self {
+        self.attrs.response_port = Some(port);
+        self
+    }
+}
+
+/// Discover the public address of `local` via a single UDP STUN binding.
+#[cfg(feature = "udp")]
+pub async fn udp(local: &LocalAddr, stun: &RemoteAddr) -> Result<MappingInfo, Error> {
+    udp_with(local, stun, ProbeOptions::new()).await
+}
+
+/// Like [`udp`], with extra request attributes.
+#[cfg(feature = "udp")]
+pub async fn udp_with(
+    local: &LocalAddr,
+    stun: &RemoteAddr,
+    opts: ProbeOptions,
+) -> Result<MappingInfo, Error> {
+    let socket = local.socket(Protocol::Udp).await.map_err(Error::Socket)?;
+    let local_addr = socket
+        .local_addr()
+        .map_err(Error::Socket)?
+        .as_socket()
+        .unwrap();
+    let socket = crate::net::udp_socket(socket).map_err(Error::Socket)?;
+
+    let stun_addr = stun.resolve(local).await?;
+    let stun_socket = crate::stun::StunUdpSocket::new(&socket, stun_addr)
+        .await
+        .map_err(Error::Connection)?;
+    let pub_addr =
+        crate::stun::udp_socket_addr(stun_socket, opts.attrs, crate::stun::TIMEOUT_DURATION)
+            .await?;
+
+    Ok(MappingInfo::new(pub_addr, local_addr))
+}
+
+/// Discover the public address of `local` via a single TCP STUN binding.
+#[cfg(feature = "tcp")]
+pub async fn tcp(local: &LocalAddr, stun: &RemoteAddr) -> Result<MappingInfo, Error> {
+    tcp_with(local, stun, ProbeOptions::new()).await
+}
+
+/// Like [`tcp`], with extra request attributes.
+///
+/// Over TCP the response always returns on the same connection, so
+/// RESPONSE-PORT is only meaningful to check server support.
+#[cfg(feature = "tcp")]
+pub async fn tcp_with(
+    local: &LocalAddr,
+    stun: &RemoteAddr,
+    opts: ProbeOptions,
+) -> Result<MappingInfo, Error> {
+    let socket = local.socket(Protocol::Tcp).await.map_err(Error::Socket)?;
+    let local_addr = socket
+        .local_addr()
+        .map_err(Error::Socket)?
+        .as_socket()
+        .unwrap();
+
+    // close with RST: a TIME_WAIT from this probe would block the next
+    // connection from the same port to the same server
+    socket
+        .set_linger(Some(std::time::Duration::ZERO))
+        .map_err(Error::Socket)?;
+
+    let stun_addr = stun.resolve(local).await?;
+    let stream = crate::net::connect_remote(socket, stun_addr)
+        .await
+        .map_err(Error::Connection)?;
+    let pub_addr =
+        crate::stun::tcp_socket_addr(stream, opts.attrs, crate::stun::TIMEOUT_DURATION).await?;
+
+    Ok(MappingInfo::new(pub_addr, local_addr))
+}
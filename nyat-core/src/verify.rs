@@ -0,0 +1,136 @@
+//! Peer-assisted mapping verification.
+//!
+//! Two nyat instances confirm each other's mappings from the outside: the
+//! verifier connects to the peer's *claimed* public address and sends
+//! `NYAT-VERIFY <nonce>`; the peer's mapper, built with
+//! [`verify_responder`](crate::mapper::MapperBuilder::verify_responder),
+//! answers `NYAT-ECHO <nonce>` from its mapped port. The nonce is 24 hex
+//! digits. Over TCP there is one exchange per connection; over UDP one
+//! datagram each way, retried on loss.
+
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "tcp")]
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::time::timeout;
+
+use crate::error::Error;
+use crate::net::{LocalAddr, Protocol, RemoteAddr};
+
+const REQ_PREFIX: &[u8] = b"NYAT-VERIFY ";
+const RESP_PREFIX: &[u8] = b"NYAT-ECHO ";
+const NONCE_LEN: usize = 24;
+const REQ_LEN: usize = REQ_PREFIX.len() + NONCE_LEN;
+const RESP_LEN: usize = RESP_PREFIX.len() + NONCE_LEN;
+
+const TIMEOUT_DURATION: Duration = Duration::from_secs(5);
+#[cfg(feature = "udp")]
+const UDP_ATTEMPTS: usize = 3;
+
+fn request() -> ([u8; REQ_LEN], [u8; NONCE_LEN]) {
+    const HEX: &[u8; 16] = b"0123456789abcdef";
+    let mut nonce = [0u8; NONCE_LEN];
+    for (pair, b) in nonce.chunks_exact_mut(2).zip(crate::stun::random_tx_id()) {
+        pair[0] = HEX[(b >> 4) as usize];
+        pair[1] = HEX[(b & 0xf) as usize];
+    }
+    let mut req = [0u8; REQ_LEN];
+    req[..REQ_PREFIX.len()].copy_from_slice(REQ_PREFIX);
+    req[REQ_PREFIX.len()..].copy_from_slice(&nonce);
+    (req, nonce)
+}
+
+/// Build the echo for a verification request, or `None` if `req` is not one.
+pub(crate) fn answer(req: &[u8]) -> Option<[u8; RESP_LEN]> {
+    let nonce = req.strip_prefix(REQ_PREFIX)?;
+    if nonce.len() != NONCE_LEN || !nonce.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    let mut resp = [0u8; RESP_LEN];
+    resp[..RESP_PREFIX.len()].copy_from_slice(RESP_PREFIX);
+    resp[RESP_PREFIX.len()..].copy_from_slice(nonce);
+    Some(resp)
+}
+
+/// Answer one verification request on an accepted connection.
+#[cfg(feature = "tcp")]
+pub(crate) async fn respond_tcp(mut stream: tokio::net::TcpStream) -> std::io::Result<()> {
+    let mut req = [0u8; REQ_LEN];
+    timeout(TIMEOUT_DURATION, stream.read_exact(&mut req))
+        .await
+        .map_err(|_| timed_out())??;
+    if let Some(resp) = answer(&req) {
+        stream.write_all(&resp).await?;
+    }
+    Ok(())
+}
+
+fn check(resp: &[u8], nonce: &[u8; NONCE_LEN]) -> Result<(), Error> {
+    match resp.strip_prefix(RESP_PREFIX) {
+        Some(echo) if echo == nonce => Ok(()),
+        _ => Err(Error::VerifyMismatch),
+    }
+}
+
+fn timed_out() -> std::io::Error {
+    std::io::ErrorKind::TimedOut.into()
+}
+
+/// Verify `peer` answers on its claimed TCP address. Returns the round-trip time.
+///
+/// The peer is resolved and dialed like a mapper's remotes from `local`
+/// (its DNS servers, interface and firewall mark), but from an ephemeral
+/// port.
+#[cfg(feature = "tcp")]
+pub async fn tcp(local: &LocalAddr, peer: &RemoteAddr) -> Result<Duration, Error> {
+    let local = local.with_port(0);
+    let addr = peer.resolve(&local).await?;
+    let socket = local.socket(Protocol::Tcp).await.map_err(Error::Socket)?;
+    let start = Instant::now();
+    let (req, nonce) = request();
+
+    let resp = timeout(TIMEOUT_DURATION, async {
+        let mut stream = crate::net::connect_remote(socket, addr).await?;
+        stream.write_all(&req).await?;
+        let mut resp = [0u8; RESP_LEN];
+        stream.read_exact(&mut resp).await?;
+        Ok::<_, std::io::Error>(resp)
+    })
+    .await
+    .map_err(|_| timed_out())
+    .flatten()
+    .map_err(Error::Connection)?;
+
+    check(&resp, &nonce)?;
+    Ok(start.elapsed())
+}
+
+/// Verify `peer` answers on its claimed UDP address. Returns the round-trip time.
+///
+/// Resolves and sends like [`tcp`], from an ephemeral port of `local`.
+#[cfg(feature = "udp")]
+pub async fn udp(local: &LocalAddr, peer: &RemoteAddr) -> Result<Duration, Error> {
+    let local = local.with_port(0);
+    let addr = peer.resolve(&local).await?;
+    let socket = local
+        .udp_socket_from_addr(local.addr())
+        .await
+        .map_err(Error::Socket)?;
+    socket.connect(addr).await.map_err(Error::Connection)?;
+
+    let (req, nonce) = request();
+    let mut buf = [0u8; RESP_LEN + 1];
+    for _ in 0..UDP_ATTEMPTS {
+        let start = Instant::now();
+        socket.send(&req).await.map_err(Error::Connection)?;
+        match timeout(TIMEOUT_DURATION, socket.recv(&mut buf)).await {
+            Ok(Ok(len)) => {
+                check(&buf[..len], &nonce)?;
+                return Ok(start.elapsed());
+            }
+            Ok(Err(e)) => return Err(Error::Connection(e)),
+            Err(_) => continue,
+        }
+    }
+    Err(Error::Connection(timed_out()))
+}
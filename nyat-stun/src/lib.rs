@@ -0,0 +1,277 @@
+//! STUN Binding codec (RFC 5389) — the one nyat uses.
+//!
+//! `no_std`, no allocation, no I/O: requests are encoded into a caller
+//! buffer and responses parsed from a byte slice. Only Binding Request
+//! (optionally with the RFC 5780 PADDING and RESPONSE-PORT attributes) and
+//! MAPPED-ADDRESS / XOR-MAPPED-ADDRESS / ERROR-CODE in responses are
+//! supported. The transaction ID comes from the caller, since there is no
+//! portable randomness source here.
+//!
+//! # Examples
+//!
+//! ```
+//! use nyat_stun::{BindingRequest, HEADER_SIZE};
+//!
+//! let tx_id = [7u8; 12]; // use random bytes
+//! let req = BindingRequest::new();
+//! let mut buf = [0u8; HEADER_SIZE];
+//! let len = req.encode(&tx_id, &mut buf).unwrap();
+//! // send `buf[..len]`, receive into a `req.max_response_len()` buffer, then:
+//! # let response = [
+//! #     0x01, 0x01, 0x00, 0x0c, 0x21, 0x12, 0xa4, 0x42, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7,
+//! #     0x00, 0x20, 0x00, 0x08, 0x00, 0x01, 0xe1, 0xba, 0x5e, 0x12, 0xa4, 0x43,
+//! # ];
+//! let addr = nyat_stun::parse_response(&response, &tx_id).unwrap();
+//! assert_eq!(addr, "127.0.0.1:49320".parse().unwrap());
+//! ```
+
+#![no_std]
+
+use core::fmt;
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+/// Size of the fixed STUN message header.
+pub const HEADER_SIZE: usize = 20;
+/// Largest response body accepted on top of any echoed PADDING.
+pub const MAX_BODY_SIZE: usize = 2048;
+
+const MAGIC_COOKIE: u32 = 0x2112_A442;
+
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_ERROR_RESPONSE: u16 = 0x0111;
+
+const ATTR_MAPPED_ADDRESS: u16 = 0x0001;
+const ATTR_ERROR_CODE: u16 = 0x0009;
+const ATTR_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+const ATTR_PADDING: u16 = 0x0026;
+const ATTR_RESPONSE_PORT: u16 = 0x0027;
+const FAMILY_IPV4: u8 = 0x01;
+const FAMILY_IPV6: u8 = 0x02;
+
+/// 96-bit STUN transaction ID.
+pub type TransactionId = [u8; 12];
+
+/// Codec error.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The response could not be parsed (missing or invalid attributes).
+    Malformed,
+    /// The response transaction ID did not match the request.
+    TransactionIdMismatch,
+    /// The server answered with a Binding Error Response and this code.
+    ErrorResponse(u16),
+    /// The output buffer cannot hold the encoded request.
+    BufferTooSmall,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Malformed => f.write_str("malformed STUN response"),
+            Self::TransactionIdMismatch => f.write_str("STUN transaction ID mismatch"),
+            Self::ErrorResponse(code) => write!(f, "STUN error response {code}"),
+            Self::BufferTooSmall => f.write_str("buffer too small for STUN request"),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+/// A Binding Request with optional RFC 5780 attributes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BindingRequest {
+    /// PADDING value length in bytes; `0` omits the attribute. Clamped to
+    /// [`MAX_PADDING`](Self::MAX_PADDING) when encoding.
+    pub padding: u16,
+    /// RESPONSE-PORT: ask the server to reply to this port.
+    pub response_port: Option<u16>,
+}
+
+impl BindingRequest {
+    /// Largest PADDING length, so that the request fits in a UDP datagram.
+    pub const MAX_PADDING: u16 = 65_400;
+
+    /// A plain Binding Request.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            padding: 0,
+            response_port: None,
+        }
+    }
+
+    const fn padding_len(&self) -> usize {
+        if self.padding > Self::MAX_PADDING {
+            Self::MAX_PADDING as usize
+        } else {
+            self.padding as usize
+        }
+    }
+
+    /// Length of the encoded request in bytes.
+    #[must_use]
+    pub const fn encoded_len(&self) -> usize {
+        let mut len = HEADER_SIZE;
+        if self.response_port.is_some() {
+            len += 8;
+        }
+        let padding = self.padding_len();
+        if padding > 0 {
+            len += 4 + padding.next_multiple_of(4);
+        }
+        len
+    }
+
+    /// Largest response to this request worth accepting: servers may echo
+    /// PADDING back.
+    #[must_use]
+    pub const fn max_response_len(&self) -> usize {
+        HEADER_SIZE + MAX_BODY_SIZE + self.padding_len()
+    }
+
+    /// Encode into `buf`, returning the number of bytes written
+    /// ([`encoded_len`](Self::encoded_len)).
+    pub fn encode(&self, tx_id: &TransactionId, buf: &mut [u8]) -> Result<usize, Error> {
+        let len = self.encoded_len();
+        let buf = buf.get_mut(..len).ok_or(Error::BufferTooSmall)?;
+        buf[0..2].copy_from_slice(&BINDING_REQUEST.to_be_bytes());
+        buf[2..4].copy_from_slice(&((len - HEADER_SIZE) as u16).to_be_bytes());
+        buf[4..8].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        buf[8..20].copy_from_slice(tx_id);
+
+        let mut pos = HEADER_SIZE;
+        if let Some(port) = self.response_port {
+            let mut value = [0u8; 4];
+            value[..2].copy_from_slice(&port.to_be_bytes());
+            pos = put_attr(buf, pos, ATTR_RESPONSE_PORT, &value);
+        }
+        let padding = self.padding_len();
+        if padding > 0 {
+            put_attr_header(buf, pos, ATTR_PADDING, padding);
+            buf[pos + 4..].fill(0);
+        }
+        Ok(len)
+    }
+}
+
+fn put_attr_header(buf: &mut [u8], pos: usize, attr_type: u16, len: usize) {
+    buf[pos..pos + 2].copy_from_slice(&attr_type.to_be_bytes());
+    buf[pos + 2..pos + 4].copy_from_slice(&(len as u16).to_be_bytes());
+}
+
+/// Write an attribute at `pos`, returning the position after it.
+fn put_attr(buf: &mut [u8], pos: usize, attr_type: u16, value: &[u8]) -> usize {
+    put_attr_header(buf, pos, attr_type, value.len());
+    buf[pos + 4..pos + 4 + value.len()].copy_from_slice(value);
+    // attributes padded to 4-byte boundary
+    let end = pos + 4 + value.len().next_multiple_of(4);
+    buf[pos + 4 + value.len()..end].fill(0);
+    end
+}
+
+/// Total message length announced by a STUN header, for framing on stream
+/// transports. `None` if `header` is shorter than [`HEADER_SIZE`].
+#[must_use]
+pub fn message_len(header: &[u8]) -> Option<usize> {
+    let len = header.get(2..4)?;
+    (header.len() >= HEADER_SIZE)
+        .then(|| HEADER_SIZE + u16::from_be_bytes([len[0], len[1]]) as usize)
+}
+
+/// Parse a Binding (Error) Response to the request with `tx_id`, returning
+/// the mapped address.
+///
+/// XOR-MAPPED-ADDRESS and MAPPED-ADDRESS are accepted, whichever comes
+/// first; an error response yields [`Error::ErrorResponse`].
+pub fn parse_response(data: &[u8], tx_id: &TransactionId) -> Result<SocketAddr, Error> {
+    if data.len() < HEADER_SIZE {
+        return Err(Error::Malformed);
+    }
+    if data[8..20] != *tx_id {
+        return Err(Error::TransactionIdMismatch);
+    }
+
+    let body_len = u16::from_be_bytes([data[2], data[3]]) as usize;
+    let body = data
+        .get(HEADER_SIZE..HEADER_SIZE + body_len)
+        .ok_or(Error::Malformed)?;
+    let is_error = u16::from_be_bytes([data[0], data[1]]) == BINDING_ERROR_RESPONSE;
+
+    let mut offset = 0;
+    while offset + 4 <= body.len() {
+        let attr_type = u16::from_be_bytes([body[offset], body[offset + 1]]);
+        let attr_len = u16::from_be_bytes([body[offset + 2], body[offset + 3]]) as usize;
+        let value = body
+            .get(offset + 4..offset + 4 + attr_len)
+            .ok_or(Error::Malformed)?;
+
+        match attr_type {
+            ATTR_ERROR_CODE if is_error && value.len() >= 4 => {
+                let code = u16::from(value[2] & 0x07) * 100 + u16::from(value[3]);
+                return Err(Error::ErrorResponse(code));
+            }
+            ATTR_XOR_MAPPED_ADDRESS if !is_error => return parse_xor_mapped(value, tx_id),
+            ATTR_MAPPED_ADDRESS if !is_error => return parse_mapped(value),
+            _ => {}
+        }
+
+        // attributes padded to 4-byte boundary
+        offset += 4 + ((attr_len + 3) & !3);
+    }
+
+    Err(Error::Malformed)
+}
+
+fn parse_xor_mapped(value: &[u8], tx_id: &TransactionId) -> Result<SocketAddr, Error> {
+    if value.len() < 8 {
+        return Err(Error::Malformed);
+    }
+    let family = value[1];
+    let port = u16::from_be_bytes([value[2], value[3]]) ^ (MAGIC_COOKIE >> 16) as u16;
+
+    match family {
+        FAMILY_IPV4 => {
+            let mut b = [0u8; 4];
+            b.copy_from_slice(&value[4..8]);
+            let cookie = MAGIC_COOKIE.to_be_bytes();
+            for (a, m) in b.iter_mut().zip(&cookie) {
+                *a ^= m;
+            }
+            Ok(SocketAddr::new(IpAddr::V4(Ipv4Addr::from(b)), port))
+        }
+        FAMILY_IPV6 if value.len() >= 20 => {
+            let mut b = [0u8; 16];
+            b.copy_from_slice(&value[4..20]);
+            let mut key = [0u8; 16];
+            key[..4].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+            key[4..].copy_from_slice(tx_id);
+            for (a, k) in b.iter_mut().zip(&key) {
+                *a ^= k;
+            }
+            Ok(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(b)), port))
+        }
+        _ => Err(Error::Malformed),
+    }
+}
+
+fn parse_mapped(value: &[u8]) -> Result<SocketAddr, Error> {
+    if value.len() < 8 {
+        return Err(Error::Malformed);
+    }
+    let family = value[1];
+    let port = u16::from_be_bytes([value[2], value[3]]);
+
+    match family {
+        FAMILY_IPV4 => Ok(SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::new(value[4], value[5], value[6], value[7])),
+            port,
+        )),
+        FAMILY_IPV6 if value.len() >= 20 => {
+            let mut b = [0u8; 16];
+            b.copy_from_slice(&value[4..20]);
+            Ok(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(b)), port))
+        }
+        _ => Err(Error::Malformed),
+    }
+}
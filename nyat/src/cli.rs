@@ -1,11 +1,29 @@
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::num::NonZeroUsize;
+#[cfg(any(
+    feature = "batch",
+    feature = "migrate",
+    feature = "soak",
+    feature = "support-bundle"
+))]
 use std::path::PathBuf;
 
-use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use clap::builder::{BoolishValueParser, TypedValueParser};
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use nyat_core::mapper::Ipv6ChangePolicy;
 use nyat_core::net::{IpVer, RemoteAddr};
 
-use crate::config::{TaskConfig, RunMode};
+use crate::config::{RunMode, TaskConfig};
+#[cfg(feature = "batch")]
+use crate::multi::{BatchOptions, ExitPolicy};
+#[cfg(all(unix, feature = "probe"))]
+use crate::probe::ProbeConfig;
+#[cfg(feature = "soak")]
+use crate::soak::SoakConfig;
+#[cfg(feature = "support-bundle")]
+use crate::support::BundleConfig;
+#[cfg(all(unix, feature = "probe"))]
+use nyat_core::probe::ProbeOptions;
 
 #[derive(Debug, Parser)]
 #[command(version, about, long_about = None)]
@@ -19,23 +37,114 @@ enum Command {
     /// Run a single mapping task
     Run {
         /// Protocol mode
+        #[arg(env = "NYAT_MODE")]
         mode: Mode,
 
         #[command(flatten)]
-        shared: SharedArgs,
+        shared: Box<SharedArgs>,
 
         /// HTTP server for keepalive (TCP only, addr[:port], default port: 80)
-        #[arg(short, long)]
+        #[arg(short, long, env = "NYAT_REMOTE")]
         remote: Option<String>,
 
         /// STUN check cycle: probe every N keepalive intervals (UDP only, default: 5)
-        #[arg(short, long)]
+        #[arg(short, long, env = "NYAT_COUNT")]
         count: Option<NonZeroUsize>,
     },
     /// Run multiple mapping tasks from a config file
+    #[cfg(feature = "batch")]
     Batch {
         #[arg(short, long, value_name = "FILE")]
         config: PathBuf,
+
+        /// Dry-run every task (overrides per-task `dry-run`)
+        #[arg(long)]
+        dry_run: bool,
+
+        /// When to exit because tasks failed (overrides `exit-on`, default: all)
+        #[arg(long, value_enum)]
+        exit_on: Option<ExitPolicy>,
+    },
+    /// Probe the public mapping of every address on an interface
+    #[cfg(all(unix, feature = "probe"))]
+    Probe {
+        /// Protocol mode
+        mode: Mode,
+
+        /// STUN server address (addr[:port], default port: 3478)
+        #[arg(short, long)]
+        stun: String,
+
+        /// Network interface whose addresses are probed
+        #[arg(short, long)]
+        iface: String,
+
+        /// Local port to bind on each address (default: 0)
+        #[arg(short, long, default_value_t = 0)]
+        port: u16,
+
+        /// Only probe IPv4 addresses
+        #[arg(short = '4', long, conflicts_with = "ipv6")]
+        ipv4: bool,
+
+        /// Only probe IPv6 addresses
+        #[arg(short = '6', long, conflicts_with = "ipv4")]
+        ipv6: bool,
+
+        /// Add a STUN PADDING attribute of this many bytes (request size 24 + BYTES)
+        #[arg(
+            long,
+            value_name = "BYTES",
+            value_parser = clap::value_parser!(u16).range(..=i64::from(ProbeOptions::MAX_PADDING))
+        )]
+        padding: Option<u16>,
+
+        /// Ask the STUN server to reply to this public port (RESPONSE-PORT)
+        #[arg(long, value_name = "PORT")]
+        response_port: Option<u16>,
+    },
+    /// Run both mapper modes against a loopback STUN/HTTP server
+    #[cfg(feature = "selftest")]
+    Selftest,
+    /// Replay hostile STUN responses through the parser and both mapper modes
+    #[cfg(feature = "soak")]
+    #[command(hide = true)]
+    Soak {
+        /// Time to run the mappers against hostile servers, in seconds
+        #[arg(short, long, value_name = "SECS", default_value_t = 60)]
+        duration: u64,
+
+        /// Seed for the generated junk (default: from the clock)
+        #[arg(long)]
+        seed: Option<u64>,
+
+        /// STUN responses to replay: files, or directories such as a `cargo fuzz` corpus
+        corpus: Vec<PathBuf>,
+    },
+    /// Convert a natmap/natter config or command line into a batch config
+    #[cfg(feature = "migrate")]
+    Migrate {
+        /// natmap/natter command lines, OpenWrt natmap UCI config, or natter config.json
+        file: PathBuf,
+    },
+    /// Collect diagnostics into a tar archive to attach to bug reports
+    #[cfg(feature = "support-bundle")]
+    SupportBundle {
+        /// Batch config to include (exec commands redacted) and probe the STUN servers of
+        #[arg(short, long, value_name = "FILE")]
+        config: Option<PathBuf>,
+
+        /// Additional STUN server to probe (addr[:port], default port: 3478; repeatable)
+        #[arg(short, long)]
+        stun: Vec<String>,
+
+        /// Log file to include the tail of, e.g. saved `logread -e nyat` output
+        #[arg(short, long, value_name = "FILE")]
+        log: Option<PathBuf>,
+
+        /// Output archive (default: nyat-support-<UNIX TIME>.tar)
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
     },
 }
 
@@ -47,60 +156,194 @@ enum Mode {
     Udp,
 }
 
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Ipv6Change {
+    /// Report every change
+    Report,
+    /// Ignore changes confined to the interface identifier, flow label or scope
+    Ignore,
+}
+
+impl From<Ipv6Change> for Ipv6ChangePolicy {
+    fn from(value: Ipv6Change) -> Self {
+        match value {
+            Ipv6Change::Report => Self::Report,
+            Ipv6Change::Ignore => Self::Ignore,
+        }
+    }
+}
+
 #[derive(Debug, Args)]
 struct SharedArgs {
     /// STUN server address (addr[:port], default port: 3478)
-    #[arg(short, long)]
+    #[arg(short, long, env = "NYAT_STUN")]
     stun: String,
 
     /// Local bind address ([addr:]port, default: 0)
-    #[arg(short, long, default_value = "0", value_name = "BIND")]
+    #[arg(
+        short,
+        long,
+        default_value = "0",
+        value_name = "BIND",
+        env = "NYAT_BIND"
+    )]
     bind: String,
 
     /// Keepalive interval in seconds (TCP: 30, UDP: 5)
-    #[arg(short, long)]
+    #[arg(short, long, env = "NYAT_KEEPALIVE")]
     keepalive: Option<u64>,
 
     /// Command to execute on mapping change
-    #[arg(short, long)]
+    #[arg(short, long, env = "NYAT_EXEC")]
     exec: Option<String>,
 
+    /// Alert condition: changes=N (per hour), port, prefix=LEN, prefix6=LEN (repeatable)
+    #[arg(long, value_name = "COND", env = "NYAT_ALERT", value_delimiter = ',')]
+    alert: Vec<String>,
+
+    /// Command to execute on each alert
+    #[arg(long, value_name = "CMD", env = "NYAT_ALERT_EXEC", requires = "alert")]
+    alert_exec: Option<String>,
+
     /// Prefer IPv4 for DNS resolution
-    #[arg(short = '4', long, conflicts_with = "ipv6")]
+    #[arg(
+        short = '4',
+        long,
+        env = "NYAT_IPV4",
+        value_parser = BoolishValueParser::new(),
+        conflicts_with = "ipv6"
+    )]
     ipv4: bool,
 
     /// Prefer IPv6 for DNS resolution
-    #[arg(short = '6', long, conflicts_with = "ipv4")]
+    #[arg(short = '6', long, env = "NYAT_IPV6", value_parser = BoolishValueParser::new())]
     ipv6: bool,
 
+    /// Resolve DNS and print the sockets and connections, without sending traffic
+    #[arg(long, env = "NYAT_DRY_RUN", value_parser = BoolishValueParser::new())]
+    dry_run: bool,
+
+    /// Peer nyat to verify periodically (ADDR:PORT or HOST:PORT of its public mapping)
+    #[arg(long, value_name = "PEER", env = "NYAT_VERIFY_PEER")]
+    verify_peer: Option<String>,
+
+    /// How to treat IPv6 mapping changes within the same /64 and port
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = Ipv6Change::Report,
+        env = "NYAT_IPV6_CHANGE"
+    )]
+    ipv6_change: Ipv6Change,
+
+    /// DNS server for resolving STUN/remote hosts (addr[:port], default port: 53; repeatable)
+    #[arg(long, value_name = "ADDR", env = "NYAT_DNS", value_delimiter = ',')]
+    dns: Vec<String>,
+
+    /// After repeated session teardowns, wait until the interface has been stable for SECS
+    #[arg(long, value_name = "SECS", env = "NYAT_FLAP_DAMPING")]
+    flap_damping: Option<u64>,
+
+    /// Treat the bind port as a preference: if it fails, use the first port of START-END with a stable mapping
+    #[arg(long, value_name = "START-END", env = "NYAT_PORT_FALLBACK")]
+    port_fallback: Option<String>,
+
+    /// Second STUN server for --port-fallback: a port only wins if both servers see the same mapping
+    #[arg(
+        long,
+        value_name = "ADDR",
+        env = "NYAT_PORT_FALLBACK_CHECK",
+        requires = "port_fallback"
+    )]
+    port_fallback_check: Option<String>,
+
     /// Network interface to bind to
     #[cfg(target_os = "linux")]
-    #[arg(short, long)]
+    #[arg(short, long, env = "NYAT_IFACE")]
     iface: Option<String>,
 
     /// Firewall mark for policy routing
     #[cfg(target_os = "linux")]
-    #[arg(short, long)]
+    #[arg(short, long, env = "NYAT_FWMARK")]
     fwmark: Option<u32>,
 
-    /// Force SO_REUSEPORT on existing sockets (requires root)
+    /// Routing table for this task's traffic (installs `ip rule fwmark <MARK> lookup <TABLE>`)
     #[cfg(target_os = "linux")]
-    #[arg(long)]
+    #[arg(
+        long,
+        value_name = "TABLE",
+        env = "NYAT_ROUTE_TABLE",
+        requires = "fwmark"
+    )]
+    route_table: Option<u32>,
+
+    /// Force SO_REUSEPORT on existing sockets (requires root)
+    #[cfg(all(target_os = "linux", feature = "reuse_port"))]
+    #[arg(long, env = "NYAT_FORCE_REUSE", value_parser = BoolishValueParser::new())]
     force_reuse: bool,
+
+    /// Retries of --force-reuse when the port owner re-creates its socket (default 3)
+    #[cfg(all(target_os = "linux", feature = "reuse_port"))]
+    #[arg(
+        long,
+        value_name = "N",
+        env = "NYAT_FORCE_REUSE_RETRIES",
+        requires = "force_reuse"
+    )]
+    force_reuse_retries: Option<u32>,
 }
 
 pub enum Config {
-    Single(TaskConfig),
-    Multi(PathBuf),
+    Single(Box<TaskConfig>),
+    #[cfg(feature = "batch")]
+    Multi(PathBuf, BatchOptions),
+    #[cfg(feature = "migrate")]
+    Migrate(PathBuf),
+    #[cfg(feature = "selftest")]
+    Selftest,
+    #[cfg(feature = "soak")]
+    Soak(SoakConfig),
+    #[cfg(all(unix, feature = "probe"))]
+    Probe(ProbeConfig),
+    #[cfg(feature = "support-bundle")]
+    SupportBundle(BundleConfig),
 }
 
 impl Config {
     pub fn parse() -> Self {
-        let cli = Cli::parse();
-        Self::try_from(cli).unwrap_or_else(|e| e.exit())
+        // bare `nyat` in a container: configure `run` from NYAT_* variables
+        let matches = if std::env::args_os().len() == 1 && std::env::var_os("NYAT_MODE").is_some() {
+            command().get_matches_from(["nyat", "run"])
+        } else {
+            command().get_matches()
+        };
+        Cli::from_arg_matches(&matches)
+            .and_then(Self::try_from)
+            .unwrap_or_else(|e| e.exit())
     }
 }
 
+/// [`Cli::command`], ignoring a falsey `NYAT_IPV4`/`NYAT_IPV6`: clap counts
+/// a flag set from the environment as present whatever its value, so
+/// `NYAT_IPV6=0` would otherwise conflict with `-4`.
+fn command() -> clap::Command {
+    let falsey = |var: &std::ffi::OsStr| {
+        std::env::var_os(var).is_some_and(|value| {
+            BoolishValueParser::new()
+                .parse_ref(&Cli::command(), None, &value)
+                .is_ok_and(|set| !set)
+        })
+    };
+    Cli::command().mut_subcommand("run", |run| {
+        ["ipv4", "ipv6"].into_iter().fold(run, |run, id| {
+            run.mut_arg(id, |arg| match arg.get_env() {
+                Some(var) if falsey(var) => arg.env(None),
+                _ => arg,
+            })
+        })
+    })
+}
+
 impl TryFrom<Cli> for Config {
     type Error = clap::Error;
     fn try_from(value: Cli) -> Result<Self, Self::Error> {
@@ -126,7 +369,7 @@ impl TryFrom<Cli> for Config {
                         let remote_str = remote.ok_or_else(|| {
                             Cli::command().error(
                                 clap::error::ErrorKind::MissingRequiredArgument,
-                                "TCP mode requires --remote (-r)",
+                                "TCP mode requires --remote (-r) or NYAT_REMOTE",
                             )
                         })?;
                         let remote = parse_with_default_port(
@@ -148,30 +391,154 @@ impl TryFrom<Cli> for Config {
                     }
                 };
 
+                let verify_peer = shared
+                    .verify_peer
+                    .as_deref()
+                    .map(|s| {
+                        let ver = Some(if shared.ipv6 { IpVer::V6 } else { IpVer::V4 });
+                        crate::config::parse_peer(s, ver)
+                    })
+                    .transpose()
+                    .map_err(|e| {
+                        Cli::command().error(clap::error::ErrorKind::InvalidValue, e.to_string())
+                    })?;
+
+                let dns = shared
+                    .dns
+                    .iter()
+                    .map(|s| crate::config::parse_dns_server(s))
+                    .collect::<Result<_, _>>()
+                    .map_err(|e| {
+                        Cli::command().error(clap::error::ErrorKind::InvalidValue, e.to_string())
+                    })?;
+
+                let alert = crate::config::parse_alerts(&shared.alert).map_err(|e| {
+                    Cli::command().error(clap::error::ErrorKind::InvalidValue, e.to_string())
+                })?;
+
+                let port_fallback = shared
+                    .port_fallback
+                    .as_deref()
+                    .map(crate::config::parse_port_range)
+                    .transpose()
+                    .map_err(|e| {
+                        Cli::command().error(clap::error::ErrorKind::InvalidValue, e.to_string())
+                    })?;
+                let port_fallback_check = shared
+                    .port_fallback_check
+                    .as_deref()
+                    .map(|s| parse_with_default_port(s, STUN_PORT, shared.ipv4, shared.ipv6))
+                    .transpose()?;
+
                 #[cfg(target_os = "linux")]
                 if let Some(ref name) = shared.iface {
                     crate::config::check_iface(name).map_err(|e| {
-                        Cli::command()
-                            .error(clap::error::ErrorKind::InvalidValue, e.to_string())
+                        Cli::command().error(clap::error::ErrorKind::InvalidValue, e.to_string())
                     })?;
                 }
 
-                Ok(Config::Single(TaskConfig {
+                Ok(Config::Single(Box::new(TaskConfig {
                     mode,
                     bind,
                     stun,
                     keepalive: shared.keepalive.map(std::time::Duration::from_secs),
                     exec: shared.exec,
+                    alert,
+                    alert_exec: shared.alert_exec,
+                    dry_run: shared.dry_run,
+                    verify_peer,
+                    ipv6_change: shared.ipv6_change.into(),
+                    dns,
+                    flap_damping: shared.flap_damping.map(std::time::Duration::from_secs),
+                    port_fallback,
+                    port_fallback_check,
                     #[cfg(target_os = "linux")]
                     iface: shared.iface,
                     #[cfg(target_os = "linux")]
                     fwmark: shared.fwmark,
                     #[cfg(target_os = "linux")]
+                    route_table: shared.route_table,
+                    #[cfg(all(target_os = "linux", feature = "reuse_port"))]
                     force_reuse: shared.force_reuse,
-                }))
+                    #[cfg(all(target_os = "linux", feature = "reuse_port"))]
+                    force_reuse_retries: shared.force_reuse_retries,
+                })))
             }
 
-            Command::Batch { config } => Ok(Config::Multi(config)),
+            #[cfg(feature = "batch")]
+            Command::Batch {
+                config,
+                dry_run,
+                exit_on,
+            } => Ok(Config::Multi(config, BatchOptions { dry_run, exit_on })),
+            #[cfg(feature = "migrate")]
+            Command::Migrate { file } => Ok(Config::Migrate(file)),
+            #[cfg(feature = "selftest")]
+            Command::Selftest => Ok(Config::Selftest),
+            #[cfg(feature = "soak")]
+            Command::Soak {
+                duration,
+                seed,
+                corpus,
+            } => Ok(Config::Soak(SoakConfig {
+                duration: std::time::Duration::from_secs(duration),
+                seed,
+                corpus,
+            })),
+            #[cfg(feature = "support-bundle")]
+            Command::SupportBundle {
+                config,
+                stun,
+                log,
+                output,
+            } => Ok(Config::SupportBundle(BundleConfig {
+                config,
+                stun: stun
+                    .iter()
+                    .map(|s| parse_with_default_port(s, STUN_PORT, true, false))
+                    .collect::<Result<_, _>>()?,
+                log,
+                output,
+            })),
+
+            #[cfg(all(unix, feature = "probe"))]
+            Command::Probe {
+                mode,
+                stun,
+                iface,
+                port,
+                ipv4,
+                ipv6,
+                padding,
+                response_port,
+            } => {
+                #[cfg(target_os = "linux")]
+                crate::config::check_iface(&iface).map_err(|e| {
+                    Cli::command().error(clap::error::ErrorKind::InvalidValue, e.to_string())
+                })?;
+
+                let family = match (ipv4, ipv6) {
+                    (true, _) => Some(IpVer::V4),
+                    (_, true) => Some(IpVer::V6),
+                    _ => None,
+                };
+                let mut options = ProbeOptions::new();
+                if let Some(len) = padding {
+                    options = options.padding(len);
+                }
+                if let Some(port) = response_port {
+                    options = options.response_port(port);
+                }
+                Ok(Config::Probe(ProbeConfig {
+                    udp: matches!(mode, Mode::Udp),
+                    options,
+                    iface,
+                    port,
+                    family,
+                    stun_v4: parse_with_default_port(&stun, STUN_PORT, true, false)?,
+                    stun_v6: parse_with_default_port(&stun, STUN_PORT, false, true)?,
+                }))
+            }
         }
     }
 }
@@ -194,10 +561,10 @@ fn parse_bind(s: &str, ipv6: bool) -> Result<SocketAddr, clap::Error> {
     }
 }
 
-const STUN_PORT: u16 = 3478;
+pub(crate) const STUN_PORT: u16 = 3478;
 const REMOTE_PORT: u16 = 80;
 
-fn parse_with_default_port(
+pub(crate) fn parse_with_default_port(
     s: &str,
     default_port: u16,
     v4: bool,
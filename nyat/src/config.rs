@@ -1,9 +1,10 @@
 use std::net::SocketAddr;
 use std::num::NonZeroUsize;
+use std::ops::RangeInclusive;
 use std::time::Duration;
 
-use nyat_core::mapper::{Mapper, MapperBuilder};
-use nyat_core::net::{LocalAddr, RemoteAddr};
+use nyat_core::mapper::{AlertRules, FlapDamping, Ipv6ChangePolicy, Mapper, MapperBuilder};
+use nyat_core::net::{IpVer, LocalAddr, RemoteAddr};
 
 /// Validate that an interface name fits within `IFNAMSIZ` (16 bytes).
 #[cfg(target_os = "linux")]
@@ -16,6 +17,70 @@ pub(crate) fn check_iface(name: &str) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Parse a DNS server as `ADDR` or `ADDR:PORT` (default port 53).
+pub(crate) fn parse_dns_server(s: &str) -> anyhow::Result<SocketAddr> {
+    const DNS_PORT: u16 = 53;
+    if let Ok(ip) = s.parse::<std::net::IpAddr>() {
+        return Ok(SocketAddr::new(ip, DNS_PORT));
+    }
+    s.parse::<SocketAddr>()
+        .map_err(|_| anyhow::anyhow!("invalid DNS server '{s}': expected ADDR or ADDR:PORT"))
+}
+
+/// Parse a verification peer as `ADDR:PORT` or `HOST:PORT`; unlike
+/// servers, a peer has no default port.
+pub(crate) fn parse_peer(s: &str, ver: Option<IpVer>) -> anyhow::Result<RemoteAddr> {
+    if let Ok(addr) = s.parse::<SocketAddr>() {
+        return Ok(RemoteAddr::from_addr(addr));
+    }
+    let invalid = || anyhow::anyhow!("invalid peer '{s}': expected ADDR:PORT or HOST:PORT");
+    match s.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() && !host.contains(':') => {
+            let port = port.parse().map_err(|_| invalid())?;
+            Ok(RemoteAddr::from_host(host, port, ver))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+/// Parse alert conditions: `changes=N`, `port`, `prefix=LEN` (IPv4) and
+/// `prefix6=LEN`.
+pub(crate) fn parse_alerts(conds: &[String]) -> anyhow::Result<AlertRules> {
+    let mut rules = AlertRules::new();
+    for cond in conds {
+        let (name, value) = match cond.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (cond.as_str(), None),
+        };
+        let num = |max: u32| -> anyhow::Result<u32> {
+            value
+                .and_then(|v| v.parse().ok())
+                .filter(|&n| n <= max)
+                .ok_or_else(|| anyhow::anyhow!("invalid alert '{cond}': expected {name}=0..{max}"))
+        };
+        rules = match name {
+            "changes" => rules.max_changes_per_hour(num(u32::MAX)? as usize),
+            "port" if value.is_none() => rules.port_mismatch(true),
+            "prefix" => rules.prefix_v4(num(32)? as u8),
+            "prefix6" => rules.prefix_v6(num(128)? as u8),
+            _ => anyhow::bail!(
+                "invalid alert '{cond}': expected changes=N, port, prefix=LEN or prefix6=LEN"
+            ),
+        };
+    }
+    Ok(rules)
+}
+
+/// Parse a port range as `START-END`, e.g. `40000-40100`.
+pub(crate) fn parse_port_range(s: &str) -> anyhow::Result<RangeInclusive<u16>> {
+    s.split_once('-')
+        .and_then(|(start, end)| Some(start.trim().parse().ok()?..=end.trim().parse().ok()?))
+        .filter(|r: &RangeInclusive<u16>| *r.start() > 0 && !r.is_empty())
+        .ok_or_else(|| {
+            anyhow::anyhow!("invalid port range '{s}': expected START-END, e.g. 40000-40100")
+        })
+}
+
 /// Resolved configuration for a single mapping task.
 #[non_exhaustive]
 pub struct TaskConfig {
@@ -24,17 +89,43 @@ pub struct TaskConfig {
     pub stun: RemoteAddr,
     pub keepalive: Option<Duration>,
     pub exec: Option<String>,
+    /// Anomaly alert conditions.
+    pub alert: AlertRules,
+    /// Command to run for each alert.
+    pub alert_exec: Option<String>,
+    /// Print the resolved plan instead of running.
+    pub dry_run: bool,
+    /// Peer nyat to verify; also enables answering verification requests.
+    pub verify_peer: Option<RemoteAddr>,
+    /// Policy for IPv6 changes confined to the interface identifier.
+    pub ipv6_change: Ipv6ChangePolicy,
+    /// DNS servers for this task; empty uses the system resolver.
+    pub dns: Vec<SocketAddr>,
+    /// Hold back rebuilds after repeated teardowns until the interface has
+    /// been stable this long.
+    pub flap_damping: Option<Duration>,
+    /// Ports to try if the bind port cannot be bound or its mapping is not
+    /// stable.
+    pub port_fallback: Option<RangeInclusive<u16>>,
+    /// Second STUN server checking `port_fallback` candidates.
+    pub port_fallback_check: Option<RemoteAddr>,
     #[cfg(target_os = "linux")]
     pub iface: Option<String>,
     #[cfg(target_os = "linux")]
     pub fwmark: Option<u32>,
     #[cfg(target_os = "linux")]
+    pub route_table: Option<u32>,
+    #[cfg(all(target_os = "linux", feature = "reuse_port"))]
     pub force_reuse: bool,
+    /// Retries of `force_reuse`; `None` for the default.
+    #[cfg(all(target_os = "linux", feature = "reuse_port"))]
+    pub force_reuse_retries: Option<u32>,
 }
 
 impl TaskConfig {
-    pub fn into_mapper(self) -> Mapper {
-        let mut local = LocalAddr::new(self.bind);
+    /// The task's local address: bind address, DNS servers and socket options.
+    pub fn local(&self) -> LocalAddr {
+        let mut local = LocalAddr::new(self.bind).with_dns_servers(self.dns.clone());
         #[cfg(target_os = "linux")]
         {
             if let Some(fmark) = self.fwmark {
@@ -43,27 +134,61 @@ impl TaskConfig {
             if let Some(ref iface) = self.iface {
                 local = local.with_iface(iface.as_bytes());
             }
+            if let Some(table) = self.route_table {
+                local = local.with_route_table(table);
+            }
+            #[cfg(feature = "reuse_port")]
             if self.force_reuse {
-                local = local.force_reuse_port();
+                local = match self.force_reuse_retries {
+                    Some(retries) => local.force_reuse_port_retries(retries),
+                    None => local.force_reuse_port(),
+                };
             }
         }
+        local
+    }
 
+    pub fn into_mapper(self) -> Mapper {
+        let local = self.local();
+        let verify = self.verify_peer.is_some();
         match self.mode {
             RunMode::Tcp { remote } => {
-                let mut builder = MapperBuilder::new_tcp(local, self.stun, remote);
+                let mut builder = MapperBuilder::new_tcp(local, self.stun, remote)
+                    .verify_responder(verify)
+                    .ipv6_change_policy(self.ipv6_change);
                 if let Some(keepalive) = self.keepalive {
                     builder = builder.interval(keepalive);
                 }
+                if let Some(settle) = self.flap_damping {
+                    builder = builder.flap_damping(FlapDamping::new(settle));
+                }
+                if let Some(ports) = self.port_fallback {
+                    builder = builder.port_fallback(ports);
+                }
+                if let Some(stun) = self.port_fallback_check {
+                    builder = builder.port_fallback_check(stun);
+                }
                 builder.build().into()
             }
             RunMode::Udp { count } => {
-                let mut builder = MapperBuilder::new_udp(local, self.stun);
+                let mut builder = MapperBuilder::new_udp(local, self.stun)
+                    .verify_responder(verify)
+                    .ipv6_change_policy(self.ipv6_change);
                 if let Some(count) = count {
                     builder = builder.check_per_tick(count);
                 }
                 if let Some(keepalive) = self.keepalive {
                     builder = builder.interval(keepalive);
                 }
+                if let Some(settle) = self.flap_damping {
+                    builder = builder.flap_damping(FlapDamping::new(settle));
+                }
+                if let Some(ports) = self.port_fallback {
+                    builder = builder.port_fallback(ports);
+                }
+                if let Some(stun) = self.port_fallback_check {
+                    builder = builder.port_fallback_check(stun);
+                }
                 builder.build().into()
             }
         }
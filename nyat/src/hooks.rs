@@ -1,17 +1,68 @@
 mod exec;
 
 use exec::ExecHook;
-use nyat_core::mapper::{MappingHandler, MappingInfo};
+use nyat_core::mapper::{Alert, AlertMonitor, AlertRules, MappingHandler, MappingInfo};
+
+type AlertSink = Box<dyn FnMut(&Alert) + Send>;
 
 pub(crate) struct Hooks {
     exec: Option<ExecHook>,
+    alerts: Option<AlertMonitor<AlertSink>>,
 }
 
 impl Hooks {
     pub fn new(exec: Option<String>) -> Self {
         Self {
             exec: exec.map(ExecHook::new),
+            alerts: None,
+        }
+    }
+
+    /// Report alerts on stderr, prefixed with `prefix`, and run
+    /// `alert_exec` for each.
+    pub fn with_alerts(
+        mut self,
+        rules: AlertRules,
+        alert_exec: Option<String>,
+        prefix: &str,
+    ) -> Self {
+        if rules.is_empty() {
+            return self;
         }
+        let prefix = prefix.to_owned();
+        let mut exec = alert_exec.map(ExecHook::new);
+        let sink: AlertSink = Box::new(move |alert| {
+            eprintln!("{prefix} alert: {alert}");
+            if let Some(exec) = &mut exec {
+                let from = match alert.kind {
+                    nyat_core::mapper::AlertKind::PrefixChanged { from, .. } => from.to_string(),
+                    _ => String::new(),
+                };
+                exec.spawn(
+                    alert.info,
+                    &[
+                        ("NYAT_ALERT_KIND", alert.name().to_owned()),
+                        ("NYAT_ALERT_MESSAGE", alert.to_string()),
+                        ("NYAT_ALERT_FROM", from),
+                    ],
+                );
+            }
+        });
+        self.alerts = Some(AlertMonitor::new(rules, sink));
+        self
+    }
+}
+
+/// Report on stderr which port [`port_fallback`] settled on.
+///
+/// [`port_fallback`]: nyat_core::mapper::MapperBuilder::port_fallback
+pub(crate) fn report_port_selected(prefix: &str, preferred: u16, selected: u16) {
+    if selected == preferred {
+        eprintln!("{prefix} using preferred port {selected}");
+    } else if preferred == 0 {
+        eprintln!("{prefix} using fallback port {selected}");
+    } else {
+        eprintln!("{prefix} port {preferred} unusable, using fallback port {selected}");
     }
 }
 
@@ -20,5 +71,8 @@ impl MappingHandler for Hooks {
         if let Some(exec) = &mut self.exec {
             exec.on_change(info);
         }
+        if let Some(alerts) = &mut self.alerts {
+            alerts.on_change(info);
+        }
     }
 }
@@ -17,10 +17,9 @@ impl ExecHook {
             children: Vec::with_capacity(4),
         }
     }
-}
 
-impl MappingHandler for ExecHook {
-    fn on_change(&mut self, info: MappingInfo) {
+    /// Run the command for `info`, with `extra` variables on top.
+    pub(super) fn spawn(&mut self, info: MappingInfo, extra: &[(&str, String)]) {
         self.reap();
         match Command::new("sh")
             .arg("-c")
@@ -29,6 +28,7 @@ impl MappingHandler for ExecHook {
             .env("NYAT_PUB_PORT", info.pub_addr.port().to_string())
             .env("NYAT_LOCAL_ADDR", info.local_addr.ip().to_string())
             .env("NYAT_LOCAL_PORT", info.local_addr.port().to_string())
+            .envs(extra.iter().map(|(k, v)| (k, v)))
             .stdin(Stdio::null())
             .stdout(Stdio::null())
             .spawn()
@@ -38,3 +38,9 @@ impl MappingHandler for ExecHook {
         }
     }
 }
+
+impl MappingHandler for ExecHook {
+    fn on_change(&mut self, info: MappingInfo) {
+        self.spawn(info, &[]);
+    }
+}
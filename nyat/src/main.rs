@@ -1,15 +1,38 @@
 mod cli;
 mod config;
 mod hooks;
+#[cfg(feature = "migrate")]
+mod migrate;
+#[cfg(feature = "batch")]
 mod multi;
+#[cfg(all(unix, feature = "probe"))]
+mod probe;
+#[cfg(feature = "selftest")]
+mod selftest;
 mod single;
+#[cfg(feature = "soak")]
+mod soak;
+#[cfg(feature = "support-bundle")]
+mod support;
+mod verify;
 
 use cli::Config;
 
 fn main() -> anyhow::Result<()> {
     match Config::parse() {
-        Config::Single(config) => single::proc(config)?,
-        Config::Multi(path) => multi::proc(path)?,
+        Config::Single(config) => single::proc(*config)?,
+        #[cfg(feature = "batch")]
+        Config::Multi(path, options) => multi::proc(path, options)?,
+        #[cfg(feature = "migrate")]
+        Config::Migrate(path) => migrate::proc(path)?,
+        #[cfg(feature = "selftest")]
+        Config::Selftest => selftest::proc()?,
+        #[cfg(feature = "soak")]
+        Config::Soak(config) => soak::proc(config)?,
+        #[cfg(all(unix, feature = "probe"))]
+        Config::Probe(config) => probe::proc(config)?,
+        #[cfg(feature = "support-bundle")]
+        Config::SupportBundle(config) => support::proc(config)?,
     }
     Ok(())
 }
@@ -0,0 +1,288 @@
+//! Convert natmap / natter configurations into a batch config.
+mod natmap;
+mod natter;
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result, bail};
+use serde::Serialize;
+
+/// One `[task.*]` entry of the generated batch config.
+#[derive(Serialize, Default)]
+#[serde(rename_all = "kebab-case")]
+struct Task {
+    mode: &'static str,
+    bind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stun_host: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stun_port: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    remote_host: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    remote_port: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keepalive: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exec: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ipv6: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    iface: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fwmark: Option<u32>,
+}
+
+impl Task {
+    /// Fill `stun-host`/`stun-port` from an `addr[:port]` string.
+    fn set_stun(&mut self, s: &str) -> Result<()> {
+        let (host, port) = split_host_port(s, STUN_PORT).context("invalid STUN server")?;
+        self.stun_host = Some(host);
+        self.stun_port = Some(port);
+        Ok(())
+    }
+
+    /// Fill `remote-host`/`remote-port` from an `addr[:port]` string.
+    fn set_remote(&mut self, s: &str) -> Result<()> {
+        let (host, port) = split_host_port(s, REMOTE_PORT).context("invalid keepalive server")?;
+        self.remote_host = Some(host);
+        self.remote_port = Some(port);
+        Ok(())
+    }
+
+    /// `-i` in both natmap and natter takes either an interface name or a local IP.
+    fn set_iface_or_ip(&mut self, s: &str, port: u16) {
+        if let Ok(ip) = s.parse() {
+            self.bind = SocketAddr::new(ip, port).to_string();
+        } else {
+            self.iface = Some(s.to_owned());
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Output {
+    version: u32,
+    task: BTreeMap<String, Task>,
+}
+
+/// Collects converted tasks and warnings about options nyat cannot express.
+#[derive(Default)]
+struct Migration {
+    tasks: BTreeMap<String, Task>,
+    warnings: Vec<String>,
+}
+
+impl Migration {
+    fn push(&mut self, prefix: &str, task: Task) {
+        let name = format!("{prefix}-{}", self.tasks.len() + 1);
+        self.insert(name, task);
+    }
+
+    /// Add `task` as `name`, or `name-2`, `name-3`, … if that is taken.
+    fn insert(&mut self, name: String, task: Task) {
+        let mut unique = name.clone();
+        let mut n = 1;
+        while self.tasks.contains_key(&unique) {
+            n += 1;
+            unique = format!("{name}-{n}");
+        }
+        if unique != name {
+            self.warn(format!(
+                "task '{name}' already exists, renamed to '{unique}'"
+            ));
+        }
+        self.tasks.insert(unique, task);
+    }
+
+    fn warn(&mut self, msg: impl Into<String>) {
+        self.warnings.push(msg.into());
+    }
+}
+
+const STUN_PORT: u16 = 3478;
+const REMOTE_PORT: u16 = 80;
+
+pub fn proc(path: PathBuf) -> Result<()> {
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+
+    let mut migration = Migration::default();
+    let trimmed = content.trim_start();
+    if trimmed.starts_with('{') {
+        natter::convert_json(&content, &mut migration).context("natter JSON config")?;
+    } else if content
+        .lines()
+        .any(|l| l.trim_start().starts_with("config natmap"))
+    {
+        natmap::convert_uci(&content, &mut migration).context("natmap UCI config")?;
+    } else {
+        convert_commands(&content, &mut migration)?;
+    }
+
+    if migration.tasks.is_empty() {
+        bail!("no natmap/natter tasks found in {}", path.display());
+    }
+
+    for w in &migration.warnings {
+        eprintln!("nyat: migrate: {w}");
+    }
+
+    let toml = toml::to_string(&Output {
+        version: crate::multi::schema::CURRENT,
+        task: migration.tasks,
+    })
+    .context("failed to serialize config")?;
+    print!("# migrated from {}\n\n{toml}", path.display());
+    Ok(())
+}
+
+/// Convert a file of natmap/natter command lines (shell scripts, init files).
+fn convert_commands(content: &str, migration: &mut Migration) -> Result<()> {
+    for (lineno, line) in logical_lines(content) {
+        let words = split_words(&line);
+        let Some(pos) = words.iter().position(|w| {
+            let prog = w.rsplit('/').next().unwrap_or(w);
+            matches!(prog, "natmap" | "natter" | "natter.py")
+        }) else {
+            continue;
+        };
+        let prog = words[pos].rsplit('/').next().unwrap_or_default();
+        let args = &words[pos + 1..];
+        let res = if prog == "natmap" {
+            natmap::convert_args(args, migration)
+        } else {
+            natter::convert_args(args, migration)
+        };
+        res.with_context(|| format!("line {lineno}"))?;
+    }
+    Ok(())
+}
+
+/// Join `\`-continued lines, dropping blanks and comments.
+fn logical_lines(content: &str) -> Vec<(usize, String)> {
+    let mut out = Vec::new();
+    let mut buf = String::new();
+    let mut start = 0;
+    for (i, line) in content.lines().enumerate() {
+        if buf.is_empty() {
+            start = i + 1;
+            if line.trim_start().starts_with('#') {
+                continue;
+            }
+        }
+        if let Some(stripped) = line.strip_suffix('\\') {
+            buf.push_str(stripped);
+            buf.push(' ');
+        } else {
+            buf.push_str(line);
+            let logical = std::mem::take(&mut buf);
+            if !logical.trim().is_empty() {
+                out.push((start, logical));
+            }
+        }
+    }
+    if !buf.trim().is_empty() {
+        out.push((start, buf));
+    }
+    out
+}
+
+/// Split a shell command line into words, honouring single/double quotes
+/// and backslash escapes. Stops at the first unquoted `#`, `;`, `&` or `|`.
+fn split_words(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut cur = String::new();
+    let mut in_word = false;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_word = true;
+                cur.extend(chars.by_ref().take_while(|&c| c != '\''));
+            }
+            '"' => {
+                in_word = true;
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => cur.extend(chars.next()),
+                        c => cur.push(c),
+                    }
+                }
+            }
+            '\\' => {
+                in_word = true;
+                cur.extend(chars.next());
+            }
+            '#' | ';' | '&' | '|' if !in_word => break,
+            c if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut cur));
+                    in_word = false;
+                }
+            }
+            c => {
+                in_word = true;
+                cur.push(c);
+            }
+        }
+    }
+    if in_word {
+        words.push(cur);
+    }
+    words
+}
+
+/// Split `addr[:port]` (including `[v6]:port` and bare IPv6) into host and port.
+fn split_host_port(s: &str, default_port: u16) -> Result<(String, u16)> {
+    if let Ok(addr) = s.parse::<SocketAddr>() {
+        return Ok((addr.ip().to_string(), addr.port()));
+    }
+    if s.parse::<std::net::IpAddr>().is_ok() {
+        return Ok((s.to_owned(), default_port));
+    }
+    match s.rsplit_once(':') {
+        Some((host, port)) => Ok((
+            host.to_owned(),
+            port.parse()
+                .with_context(|| format!("invalid port in '{s}'"))?,
+        )),
+        None => Ok((s.to_owned(), default_port)),
+    }
+}
+
+/// Minimal `getopt(3)`: split short options, allowing grouped flags (`-4u`)
+/// and attached values (`-k10`). `with_value` lists options taking an argument.
+fn getopt(args: &[String], with_value: &str) -> Result<Vec<(char, Option<String>)>> {
+    let mut opts = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let Some(flags) = arg.strip_prefix('-').filter(|f| !f.is_empty()) else {
+            bail!("unexpected argument '{arg}'");
+        };
+        for (i, c) in flags.char_indices() {
+            if with_value.contains(c) {
+                let rest = &flags[i + c.len_utf8()..];
+                let value = if rest.is_empty() {
+                    iter.next()
+                        .with_context(|| format!("option -{c} requires a value"))?
+                        .clone()
+                } else {
+                    rest.to_owned()
+                };
+                opts.push((c, Some(value)));
+                break;
+            }
+            opts.push((c, None));
+        }
+    }
+    Ok(opts)
+}
+
+/// Quote `s` for use inside an `sh -c` command string.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
@@ -0,0 +1,188 @@
+//! natmap command lines and OpenWrt `/etc/config/natmap` (UCI).
+use anyhow::{Context, Result, bail};
+
+use super::{Migration, Task, getopt, shell_quote, split_words};
+
+/// natmap runs its script as
+/// `<script> <pub-addr> <pub-port> <ip4p> <priv-port> <protocol> <priv-addr>`.
+fn exec_for(script: &str, udp: bool) -> String {
+    format!(
+        r#"{} "$NYAT_PUB_ADDR" "$NYAT_PUB_PORT" "" "$NYAT_LOCAL_PORT" {} "$NYAT_LOCAL_ADDR""#,
+        shell_quote(script),
+        if udp { "udp" } else { "tcp" },
+    )
+}
+
+/// Parse a natmap `-b <port>[-port]`; only the first port of a range is kept.
+fn parse_port(s: &str, migration: &mut Migration) -> Result<u16> {
+    let (first, rest) = s.split_once('-').unwrap_or((s, ""));
+    if !rest.is_empty() {
+        migration.warn(format!(
+            "natmap port range '{s}' is not supported, binding {first}"
+        ));
+    }
+    first
+        .parse()
+        .with_context(|| format!("invalid bind port '{s}'"))
+}
+
+/// Parse a fwmark the way natmap does (`0x` hex, leading `0` octal, decimal).
+fn parse_mark(s: &str) -> Result<u32> {
+    let res = if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        u32::from_str_radix(hex, 16)
+    } else if s.len() > 1
+        && let Some(oct) = s.strip_prefix('0')
+    {
+        u32::from_str_radix(oct, 8)
+    } else {
+        s.parse()
+    };
+    res.with_context(|| format!("invalid fwmark '{s}'"))
+}
+
+pub(super) fn convert_args(args: &[String], migration: &mut Migration) -> Result<()> {
+    let mut task = Task::default();
+    let mut udp = false;
+    let mut port = 0;
+    let mut iface = None;
+    let mut script = None;
+
+    for (opt, value) in getopt(args, "kcisheTftpb")? {
+        let value = value.unwrap_or_default();
+        match opt {
+            '4' => task.ipv6 = Some(false),
+            '6' => task.ipv6 = Some(true),
+            'u' => udp = true,
+            'd' => {}
+            'k' => task.keepalive = Some(value.parse().context("invalid -k")?),
+            's' => task.set_stun(&value)?,
+            'h' => task.set_remote(&value)?,
+            'e' => script = Some(value),
+            'i' => iface = Some(value),
+            'f' => task.fwmark = Some(parse_mark(&value)?),
+            'b' => port = parse_port(&value, migration)?,
+            't' | 'p' | 'T' => migration.warn(format!(
+                "natmap forward option -{opt} is not supported, ignored"
+            )),
+            c => migration.warn(format!("natmap option -{c} is not supported, ignored")),
+        }
+    }
+
+    if task.stun_host.is_none() {
+        bail!("natmap command has no STUN server (-s)");
+    }
+    if udp {
+        task.remote_host = None;
+        task.remote_port = None;
+    } else if task.remote_host.is_none() {
+        bail!("natmap TCP command has no HTTP server (-h)");
+    }
+
+    task.mode = if udp { "udp" } else { "tcp" };
+    task.bind = port.to_string();
+    if let Some(iface) = iface {
+        task.set_iface_or_ip(&iface, port);
+    }
+    if let Some(script) = script {
+        migration.warn("natmap ip4p script argument has no nyat equivalent, passing empty string");
+        task.exec = Some(exec_for(&script, udp));
+    }
+
+    migration.push("natmap", task);
+    Ok(())
+}
+
+/// `option <key> <value>` / `list <key> <value>` inside a UCI section.
+type UciOption = (String, String);
+
+/// Convert OpenWrt `config natmap` sections.
+///
+/// Logical interface names (`wan`) are copied verbatim; nyat needs the
+/// device name (`eth0`, `pppoe-wan`).
+pub(super) fn convert_uci(content: &str, migration: &mut Migration) -> Result<()> {
+    let mut sections: Vec<(Option<String>, Vec<UciOption>)> = Vec::new();
+    let mut in_natmap = false;
+
+    for line in content.lines() {
+        let words = split_words(line);
+        match words.as_slice() {
+            [kw, ty, rest @ ..] if kw == "config" => {
+                in_natmap = ty == "natmap";
+                if in_natmap {
+                    sections.push((rest.first().cloned(), Vec::new()));
+                }
+            }
+            [kw, key, value] if in_natmap && (kw == "option" || kw == "list") => {
+                if let Some((_, opts)) = sections.last_mut() {
+                    opts.push((key.clone(), value.clone()));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // named sections first, so generated names yield on collision
+    sections.sort_by_key(|(name, _)| name.is_none());
+    for (name, opts) in sections {
+        let get = |key: &str| {
+            opts.iter()
+                .rev()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.as_str())
+        };
+        let label = name.as_deref().unwrap_or("natmap");
+
+        if get("enable") == Some("0") {
+            migration.warn(format!("section '{label}' is disabled, skipped"));
+            continue;
+        }
+
+        let mut task = Task::default();
+        let udp = get("udp_mode") == Some("1") || get("proto") == Some("udp");
+        let port = match get("port") {
+            Some(p) => parse_port(p, migration)?,
+            None => 0,
+        };
+
+        match get("family") {
+            Some("ipv4") => task.ipv6 = Some(false),
+            Some("ipv6") => task.ipv6 = Some(true),
+            _ => {}
+        }
+        if let Some(k) = get("interval") {
+            task.keepalive = Some(k.parse().with_context(|| format!("'{label}': interval"))?);
+        }
+        let stun =
+            get("stun_server").with_context(|| format!("section '{label}' has no stun_server"))?;
+        task.set_stun(stun).with_context(|| format!("'{label}'"))?;
+        if !udp {
+            let http = get("http_server")
+                .with_context(|| format!("TCP section '{label}' has no http_server"))?;
+            task.set_remote(http)
+                .with_context(|| format!("'{label}'"))?;
+        }
+        if get("forward_target").is_some() || get("forward_port").is_some() {
+            migration.warn(format!(
+                "'{label}': port forwarding is not supported, ignored"
+            ));
+        }
+
+        task.mode = if udp { "udp" } else { "tcp" };
+        task.bind = port.to_string();
+        if let Some(iface) = get("interface") {
+            migration.warn(format!(
+                "'{label}': interface '{iface}' copied verbatim, make sure it is a device name"
+            ));
+            task.set_iface_or_ip(iface, port);
+        }
+        if let Some(script) = get("notify_script") {
+            task.exec = Some(exec_for(script, udp));
+        }
+
+        match name {
+            Some(name) => migration.insert(name, task),
+            None => migration.push("natmap", task),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,132 @@
+//! natter command lines (v2) and `config.json` (v0.9).
+use anyhow::{Context, Result, bail};
+use serde_json::Value;
+
+use super::{Migration, Task, getopt, shell_quote};
+
+/// natter runs its script as
+/// `<script> <protocol> <priv-addr> <priv-port> <pub-addr> <pub-port>`.
+fn exec_for(script: &str, udp: bool) -> String {
+    format!(
+        r#"{} {} "$NYAT_LOCAL_ADDR" "$NYAT_LOCAL_PORT" "$NYAT_PUB_ADDR" "$NYAT_PUB_PORT""#,
+        shell_quote(script),
+        if udp { "udp" } else { "tcp" },
+    )
+}
+
+pub(super) fn convert_args(args: &[String], migration: &mut Migration) -> Result<()> {
+    let mut task = Task::default();
+    let mut udp = false;
+    let mut port = 0;
+    let mut iface = None;
+    let mut script = None;
+
+    for (opt, value) in getopt(args, "kshebimtp")? {
+        let value = value.unwrap_or_default();
+        match opt {
+            'v' | 'q' => {}
+            'u' => udp = true,
+            'k' => task.keepalive = Some(value.parse().context("invalid -k")?),
+            's' if task.stun_host.is_some() => migration.warn(format!(
+                "natter fallback STUN server '{value}' dropped, nyat uses a single server"
+            )),
+            's' => task.set_stun(&value)?,
+            'h' => task.set_remote(&value)?,
+            'e' => script = Some(value),
+            'i' => iface = Some(value),
+            'b' => {
+                port = value
+                    .parse()
+                    .with_context(|| format!("invalid -b '{value}'"))?
+            }
+            'm' | 't' | 'p' | 'r' => migration.warn(format!(
+                "natter forward option -{opt} is not supported, ignored"
+            )),
+            'U' => migration.warn("natter UPnP (-U) is not supported, ignored"),
+            c => migration.warn(format!("natter option -{c} is not supported, ignored")),
+        }
+    }
+
+    if task.stun_host.is_none() {
+        bail!("natter command has no STUN server (-s); nyat has no built-in server list");
+    }
+    if udp {
+        task.remote_host = None;
+        task.remote_port = None;
+    } else if task.remote_host.is_none() {
+        bail!("natter TCP command has no keepalive server (-h)");
+    }
+
+    task.mode = if udp { "udp" } else { "tcp" };
+    task.bind = port.to_string();
+    if let Some(iface) = iface {
+        task.set_iface_or_ip(&iface, port);
+    }
+    task.exec = script.map(|s| exec_for(&s, udp));
+
+    migration.push("natter", task);
+    Ok(())
+}
+
+/// Replace natter's `{placeholder}` hook arguments with nyat environment variables.
+fn convert_hook(hook: &str, udp: bool) -> String {
+    hook.replace("{protocol}", if udp { "udp" } else { "tcp" })
+        .replace("{inner_ip}", "\"$NYAT_LOCAL_ADDR\"")
+        .replace("{inner_port}", "\"$NYAT_LOCAL_PORT\"")
+        .replace("{outer_ip}", "\"$NYAT_PUB_ADDR\"")
+        .replace("{outer_port}", "\"$NYAT_PUB_PORT\"")
+}
+
+fn strings(v: &Value) -> Vec<&str> {
+    v.as_array()
+        .map(|a| a.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default()
+}
+
+/// Convert a natter v0.9 `config.json`: one task per `open_port` entry.
+pub(super) fn convert_json(content: &str, migration: &mut Migration) -> Result<()> {
+    let config: Value = serde_json::from_str(content).context("invalid JSON")?;
+
+    let forward = &config["forward_port"];
+    if !strings(&forward["tcp"]).is_empty() || !strings(&forward["udp"]).is_empty() {
+        migration.warn("natter forward_port entries are not supported, ignored");
+    }
+
+    let hook = config["status_report"]["hook"]
+        .as_str()
+        .filter(|h| !h.is_empty());
+    let keep_alive = config["keep_alive"].as_str();
+
+    for (proto, udp) in [("tcp", false), ("udp", true)] {
+        let stun = strings(&config["stun_server"][proto]);
+        for bind in strings(&config["open_port"][proto]) {
+            let mut task = Task {
+                mode: proto,
+                ..Default::default()
+            };
+
+            let Some(first) = stun.first() else {
+                bail!("no {proto} stun_server for open_port '{bind}'");
+            };
+            task.set_stun(first)?;
+            if stun.len() > 1 {
+                migration.warn(format!(
+                    "natter {proto} fallback STUN servers dropped, using '{first}'"
+                ));
+            }
+
+            if !udp {
+                task.set_remote(keep_alive.context("keep_alive is required for tcp open_port")?)?;
+            }
+
+            task.bind = bind
+                .parse::<std::net::SocketAddr>()
+                .with_context(|| format!("invalid open_port '{bind}'"))?
+                .to_string();
+            task.exec = hook.map(|h| convert_hook(h, udp));
+
+            migration.push("natter", task);
+        }
+    }
+    Ok(())
+}
@@ -1,11 +1,30 @@
 mod handle;
+mod output;
 mod parse;
+pub(crate) mod schema;
 use anyhow::Result;
-use parse::MultiConfig;
+pub use parse::ExitPolicy;
+pub use parse::MultiConfig;
 use std::path::PathBuf;
 
-pub fn proc(path: PathBuf) -> Result<()> {
-    let config = MultiConfig::load(&path)?;
-    handle::run(config)?;
+/// Command-line overrides for `nyat batch`.
+pub struct BatchOptions {
+    pub dry_run: bool,
+    pub exit_on: Option<ExitPolicy>,
+}
+
+pub fn proc(path: PathBuf, options: BatchOptions) -> Result<()> {
+    let mut config = MultiConfig::load(&path)?;
+    if options.dry_run {
+        for task in config.tasks.values_mut() {
+            task.dry_run = true;
+        }
+    }
+    if let Some(exit_on) = options.exit_on {
+        config.exit_on = exit_on;
+    }
+    if let Some(code) = handle::run(config)? {
+        std::process::exit(code.into());
+    }
     Ok(())
 }
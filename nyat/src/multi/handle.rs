@@ -1,21 +1,31 @@
-use std::io::Write;
+use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::Result;
-use nyat_core::mapper::{Mapper, MappingHandler, MappingInfo};
+use nyat_core::mapper::{Mapper, MappingEvent, MappingHandler, MappingInfo};
 use tokio::runtime::Runtime;
 use tokio::task::JoinSet;
 
+use super::ExitPolicy;
+use super::output::Output;
 use crate::hooks::Hooks;
 
+/// How long queued output may take to flush before the process exits.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
 struct TaskHandler {
     hooks: Hooks,
-    name: String,
+    name: Arc<str>,
+    output: Output,
 }
 
 impl TaskHandler {
-    fn new(name: String, hooks: Hooks) -> Self {
-        Self { hooks, name }
+    fn new(name: String, hooks: Hooks, output: Output) -> Self {
+        Self {
+            hooks,
+            name: name.into(),
+            output,
+        }
     }
 }
 
@@ -23,19 +33,53 @@ impl MappingHandler for TaskHandler {
     fn on_change(&mut self, info: MappingInfo) {
         self.hooks.on_change(info);
 
-        let _ = writeln!(
-            std::io::stdout(),
-            "[{}] {} {} {} {}",
-            self.name,
-            info.pub_addr.ip(),
-            info.pub_addr.port(),
-            info.local_addr.ip(),
-            info.local_addr.port(),
+        self.output.mapping(
+            &self.name,
+            format!(
+                "[{}] {} {} {} {}",
+                self.name,
+                info.pub_addr.ip(),
+                info.pub_addr.port(),
+                info.local_addr.ip(),
+                info.local_addr.port(),
+            ),
         );
     }
+
+    fn on_event(&mut self, event: &MappingEvent<'_>) {
+        match *event {
+            MappingEvent::AddressChanged(info) => self.on_change(info),
+            MappingEvent::PortSelected {
+                preferred,
+                selected,
+            } => {
+                let prefix = format!("[{}]", self.name);
+                crate::hooks::report_port_selected(&prefix, preferred, selected);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Print the task's plan, each line prefixed with the task name.
+///
+/// Returns `false` if the plan could not be built.
+async fn print_plan(name: &str, mapper: &Mapper, output: &Output) -> bool {
+    match mapper.dry_run().await {
+        Ok(plan) => {
+            for line in plan.to_string().lines() {
+                output.line(format!("[{name}] {line}"));
+            }
+            true
+        }
+        Err(e) => {
+            eprintln!("[{name}] dry run failed: {:#}", anyhow::Error::from(e));
+            false
+        }
+    }
 }
 
-async fn run_task(mapper: Mapper, handler: &mut TaskHandler) {
+async fn run_task(mapper: &Mapper, handler: &mut TaskHandler) {
     loop {
         match mapper.run(handler).await {
             Ok(()) => {}
@@ -55,27 +99,91 @@ async fn run_task(mapper: Mapper, handler: &mut TaskHandler) {
     }
 }
 
-pub(super) fn run(multi_config: super::MultiConfig) -> Result<()> {
+/// Run every task until the exit policy says to stop. Dry-run tasks whose
+/// plan could not be built count as failed.
+///
+/// Returns the exit code when the batch ends because tasks failed, `None`
+/// when there was nothing to run (e.g. every task is a dry run and every
+/// plan was built).
+pub(super) fn run(multi_config: super::MultiConfig) -> Result<Option<u8>> {
     let rt = Runtime::new()?;
+    let output = Output::spawn()?;
+    let super::MultiConfig {
+        tasks,
+        exit_on,
+        exit_code,
+    } = multi_config;
 
-    rt.block_on(async {
+    let code = rt.block_on(async {
         let mut set = JoinSet::new();
+        let (mut planned, mut plans_failed) = (0usize, 0usize);
+
+        let mut tasks: Vec<_> = tasks.into_iter().collect();
+        // dry runs first, so that `exit-on = any` stops before anything runs
+        tasks.sort_unstable_by(|a, b| (!a.1.dry_run, &a.0).cmp(&(!b.1.dry_run, &b.0)));
 
-        for (name, mut config) in multi_config.tasks {
+        for (name, mut config) in tasks {
             let exec = config.exec.take();
+            let alert = config.alert;
+            let alert_exec = config.alert_exec.take();
+            let dry_run = config.dry_run;
+            let verify_peer = config.verify_peer.clone();
+            let local = config.local();
             let mapper = config.into_mapper();
-            let mut handler = TaskHandler::new(name, Hooks::new(exec));
+            if dry_run {
+                planned += 1;
+                if !print_plan(&name, &mapper, &output).await {
+                    plans_failed += 1;
+                }
+                continue;
+            }
+            if plans_failed > 0 && matches!(exit_on, ExitPolicy::Any) {
+                break;
+            }
+            let hooks = Hooks::new(exec).with_alerts(alert, alert_exec, &format!("[{name}]"));
+            let mut handler = TaskHandler::new(name, hooks, output.clone());
             set.spawn(async move {
-                run_task(mapper, &mut handler).await;
+                let prefix = format!("[{}]", handler.name);
+                tokio::select! {
+                    () = run_task(&mapper, &mut handler) => {}
+                    never = crate::verify::watch(&prefix, verify_peer, local, &mapper) => match never {},
+                }
             });
         }
 
-        while let Some(result) = set.join_next().await {
+        let total = set.len();
+        if total == 0 {
+            if plans_failed == 0 {
+                return None;
+            }
+            eprintln!(
+                "nyat: {plans_failed} of {planned} dry runs failed, exiting with status {exit_code}"
+            );
+            return Some(exit_code);
+        }
+
+        // failed plans count as failed tasks; the others only finish on a
+        // fatal error or panic
+        let total = total + plans_failed;
+        let mut failed = plans_failed;
+        while !(matches!(exit_on, ExitPolicy::Any) && failed > 0) {
+            let Some(result) = set.join_next().await else {
+                break;
+            };
             if let Err(e) = result {
                 eprintln!("task panicked: {e}");
             }
+            failed += 1;
+        }
+
+        if matches!(exit_on, ExitPolicy::Never) {
+            eprintln!("nyat: all {total} tasks failed, staying up (exit-on = never)");
+            std::future::pending::<()>().await;
         }
+        eprintln!("nyat: {failed} of {total} tasks failed, exiting with status {exit_code}");
+        Some(exit_code)
     });
 
-    Ok(())
+    output.drain(DRAIN_TIMEOUT);
+    Ok(code)
 }
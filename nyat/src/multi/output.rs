@@ -0,0 +1,145 @@
+//! Batch stdout: every task's output goes through one writer thread.
+//!
+//! Handlers run inside the keepalive loops, so they only enqueue. The queue
+//! is bounded: when stdout is slow or blocked (a consumer that stopped
+//! reading), a task's queued mapping line is overwritten by its newer one,
+//! and otherwise the oldest line is dropped. Once stdout is gone for good,
+//! output is discarded while the tasks keep running.
+use std::collections::VecDeque;
+use std::io::{BufWriter, Write};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+/// Lines queued while the writer is busy.
+const CAPACITY: usize = 256;
+
+struct Line {
+    /// Set for mapping lines, which only matter as the task's latest state.
+    task: Option<Arc<str>>,
+    text: String,
+}
+
+#[derive(Default)]
+struct State {
+    queue: VecDeque<Line>,
+    dropped: u64,
+    writing: bool,
+    closed: bool,
+}
+
+#[derive(Default)]
+struct Shared {
+    state: Mutex<State>,
+    /// Signals the writer that lines are queued.
+    queued: Condvar,
+    /// Signals [`Output::drain`] that the writer went idle.
+    idle: Condvar,
+}
+
+#[derive(Clone)]
+pub(super) struct Output {
+    shared: Arc<Shared>,
+}
+
+impl Output {
+    /// Start the writer thread.
+    pub(super) fn spawn() -> std::io::Result<Self> {
+        let shared = Arc::new(Shared::default());
+        let writer = shared.clone();
+        std::thread::Builder::new()
+            .name("nyat-stdout".into())
+            .spawn(move || write_loop(&writer))?;
+        Ok(Self { shared })
+    }
+
+    /// Queue `task`'s mapping line, replacing one still queued.
+    pub(super) fn mapping(&self, task: &Arc<str>, text: String) {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.closed {
+            return;
+        }
+        if let Some(line) = state
+            .queue
+            .iter_mut()
+            .find(|l| l.task.as_ref() == Some(task))
+        {
+            line.text = text;
+            // a burst of updates is expected to coalesce; only a full queue
+            // means stdout is falling behind
+            if state.queue.len() >= CAPACITY {
+                state.dropped += 1;
+            }
+            return;
+        }
+        push(&mut state, Some(task.clone()), text);
+        self.shared.queued.notify_one();
+    }
+
+    /// Queue a line that is never coalesced.
+    pub(super) fn line(&self, text: String) {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.closed {
+            return;
+        }
+        push(&mut state, None, text);
+        self.shared.queued.notify_one();
+    }
+
+    /// Wait up to `timeout` for queued lines to be written.
+    pub(super) fn drain(&self, timeout: Duration) {
+        let state = self.shared.state.lock().unwrap();
+        let _ = self
+            .shared
+            .idle
+            .wait_timeout_while(state, timeout, |s| {
+                !s.closed && (s.writing || !s.queue.is_empty())
+            })
+            .unwrap();
+    }
+}
+
+fn push(state: &mut State, task: Option<Arc<str>>, text: String) {
+    if state.queue.len() >= CAPACITY {
+        state.queue.pop_front();
+        state.dropped += 1;
+    }
+    state.queue.push_back(Line { task, text });
+}
+
+fn write_loop(shared: &Shared) {
+    let mut stdout = BufWriter::new(std::io::stdout());
+    loop {
+        let (lines, dropped) = {
+            let mut state = shared
+                .queued
+                .wait_while(shared.state.lock().unwrap(), |s| s.queue.is_empty())
+                .unwrap();
+            state.writing = true;
+            (
+                std::mem::take(&mut state.queue),
+                std::mem::take(&mut state.dropped),
+            )
+        };
+
+        if dropped > 0 {
+            eprintln!("nyat: stdout is not keeping up, {dropped} line(s) dropped or superseded");
+        }
+        let res = lines
+            .iter()
+            .try_for_each(|l| writeln!(stdout, "{}", l.text))
+            .and_then(|()| stdout.flush());
+
+        let mut state = shared.state.lock().unwrap();
+        state.writing = false;
+        if let Err(e) = res {
+            state.closed = true;
+            state.queue.clear();
+            drop(state);
+            shared.idle.notify_all();
+            eprintln!("nyat: stdout: {e}, discarding further output");
+            return;
+        }
+        drop(state);
+        shared.idle.notify_all();
+    }
+}
@@ -6,6 +6,7 @@ use std::path::PathBuf;
 use std::time::Duration;
 
 use anyhow::{Context, Result, bail};
+use nyat_core::mapper::Ipv6ChangePolicy;
 use nyat_core::net::{IpVer, RemoteAddr};
 use serde::Deserialize;
 
@@ -36,9 +37,26 @@ impl Server {
     }
 }
 
+/// When `nyat batch` exits because tasks failed fatally.
+#[derive(Debug, Clone, Copy, Default, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExitPolicy {
+    /// Exit as soon as any task fails
+    Any,
+    /// Exit once every task has failed
+    #[default]
+    All,
+    /// Keep running even after every task has failed
+    Never,
+}
+
+const DEFAULT_EXIT_CODE: u8 = 1;
+
 #[derive(Deserialize)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 struct BatchFile {
+    exit_on: Option<ExitPolicy>,
+    exit_code: Option<u8>,
     #[serde(default)]
     default: Defaults,
     task: HashMap<String, TaskEntry>,
@@ -53,13 +71,23 @@ struct Defaults {
     remote_port: Option<u16>,
     keepalive: Option<u64>,
     exec: Option<String>,
+    alert: Option<Vec<String>>,
+    alert_exec: Option<String>,
     ipv6: Option<bool>,
+    ipv6_change: Option<Ipv6Change>,
+    dry_run: Option<bool>,
+    dns: Option<Vec<String>>,
+    flap_damping: Option<u64>,
     #[cfg(target_os = "linux")]
     iface: Option<String>,
     #[cfg(target_os = "linux")]
     fwmark: Option<u32>,
     #[cfg(target_os = "linux")]
+    route_table: Option<u32>,
+    #[cfg(all(target_os = "linux", feature = "reuse_port"))]
     force_reuse: Option<bool>,
+    #[cfg(all(target_os = "linux", feature = "reuse_port"))]
+    force_reuse_retries: Option<u32>,
 }
 
 impl Defaults {
@@ -71,6 +99,12 @@ impl Defaults {
         let remote = Server::try_from_pair(self.remote_host, self.remote_port, "remote")
             .context("remote server")?;
 
+        let dns = self
+            .dns
+            .map(|servers| parse_dns(&servers))
+            .transpose()
+            .context("[default] dns")?;
+
         #[cfg(target_os = "linux")]
         if let Some(ref name) = self.iface {
             crate::config::check_iface(name).context("[default] iface")?;
@@ -81,13 +115,23 @@ impl Defaults {
             remote,
             keepalive: self.keepalive,
             exec: self.exec,
+            alert: self.alert,
+            alert_exec: self.alert_exec,
             ipv6: self.ipv6,
+            ipv6_change: self.ipv6_change,
+            dry_run: self.dry_run,
+            dns,
+            flap_damping: self.flap_damping,
             #[cfg(target_os = "linux")]
             iface: self.iface,
             #[cfg(target_os = "linux")]
             fwmark: self.fwmark,
             #[cfg(target_os = "linux")]
+            route_table: self.route_table,
+            #[cfg(all(target_os = "linux", feature = "reuse_port"))]
             force_reuse: self.force_reuse,
+            #[cfg(all(target_os = "linux", feature = "reuse_port"))]
+            force_reuse_retries: self.force_reuse_retries,
         })
     }
 }
@@ -97,13 +141,23 @@ struct ParsedDefaults {
     remote: Option<Server>,
     keepalive: Option<u64>,
     exec: Option<String>,
+    alert: Option<Vec<String>>,
+    alert_exec: Option<String>,
     ipv6: Option<bool>,
+    ipv6_change: Option<Ipv6Change>,
+    dry_run: Option<bool>,
+    dns: Option<Vec<SocketAddr>>,
+    flap_damping: Option<u64>,
     #[cfg(target_os = "linux")]
     iface: Option<String>,
     #[cfg(target_os = "linux")]
     fwmark: Option<u32>,
     #[cfg(target_os = "linux")]
+    route_table: Option<u32>,
+    #[cfg(all(target_os = "linux", feature = "reuse_port"))]
     force_reuse: Option<bool>,
+    #[cfg(all(target_os = "linux", feature = "reuse_port"))]
+    force_reuse_retries: Option<u32>,
 }
 
 #[derive(Deserialize, Clone, Copy)]
@@ -113,6 +167,22 @@ enum TaskMode {
     Udp,
 }
 
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+enum Ipv6Change {
+    Report,
+    Ignore,
+}
+
+impl From<Ipv6Change> for Ipv6ChangePolicy {
+    fn from(value: Ipv6Change) -> Self {
+        match value {
+            Ipv6Change::Report => Self::Report,
+            Ipv6Change::Ignore => Self::Ignore,
+        }
+    }
+}
+
 #[derive(Deserialize)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 struct TaskEntry {
@@ -124,14 +194,34 @@ struct TaskEntry {
     remote_port: Option<u16>,
     keepalive: Option<u64>,
     exec: Option<String>,
+    alert: Option<Vec<String>>,
+    alert_exec: Option<String>,
     count: Option<NonZeroUsize>,
     ipv6: Option<bool>,
+    ipv6_change: Option<Ipv6Change>,
+    dry_run: Option<bool>,
+    verify_peer: Option<String>,
+    dns: Option<Vec<String>>,
+    flap_damping: Option<u64>,
+    port_fallback: Option<String>,
+    port_fallback_check: Option<String>,
     #[cfg(target_os = "linux")]
     iface: Option<String>,
     #[cfg(target_os = "linux")]
     fwmark: Option<u32>,
     #[cfg(target_os = "linux")]
+    route_table: Option<u32>,
+    #[cfg(all(target_os = "linux", feature = "reuse_port"))]
     force_reuse: Option<bool>,
+    #[cfg(all(target_os = "linux", feature = "reuse_port"))]
+    force_reuse_retries: Option<u32>,
+}
+
+fn parse_dns(servers: &[String]) -> Result<Vec<SocketAddr>> {
+    servers
+        .iter()
+        .map(|s| crate::config::parse_dns_server(s))
+        .collect()
 }
 
 fn parse_bind(s: &str, ipv6: bool) -> Result<SocketAddr> {
@@ -201,18 +291,98 @@ impl TaskEntry {
 
         let exec = self.exec.or_else(|| defaults.exec.clone());
 
+        let alert = self
+            .alert
+            .as_ref()
+            .or(defaults.alert.as_ref())
+            .map(|conds| crate::config::parse_alerts(conds))
+            .transpose()
+            .context(ctx("alert"))?
+            .unwrap_or_default();
+        let alert_exec = self.alert_exec.or_else(|| defaults.alert_exec.clone());
+        if alert_exec.is_some() && alert.is_empty() {
+            bail!("{}", ctx("alert-exec requires alert conditions"));
+        }
+
+        let verify_peer = self
+            .verify_peer
+            .map(|s| crate::config::parse_peer(&s, ver))
+            .transpose()
+            .context(ctx("verify-peer"))?;
+
+        let dns = match self.dns {
+            Some(servers) => parse_dns(&servers).context(ctx("dns"))?,
+            None => defaults.dns.clone().unwrap_or_default(),
+        };
+
+        let port_fallback = self
+            .port_fallback
+            .as_deref()
+            .map(crate::config::parse_port_range)
+            .transpose()
+            .context(ctx("port-fallback"))?;
+        let port_fallback_check = self
+            .port_fallback_check
+            .map(|s| {
+                crate::cli::parse_with_default_port(&s, crate::cli::STUN_PORT, !ipv6, ipv6)
+                    .map_err(|_| anyhow::anyhow!("invalid address '{s}': expected HOST[:PORT]"))
+            })
+            .transpose()
+            .context(ctx("port-fallback-check"))?;
+        if port_fallback_check.is_some() && port_fallback.is_none() {
+            bail!("{}", ctx("port-fallback-check requires port-fallback"));
+        }
+
+        #[cfg(target_os = "linux")]
+        let (fwmark, route_table) = (
+            self.fwmark.or(defaults.fwmark),
+            self.route_table.or(defaults.route_table),
+        );
+        #[cfg(target_os = "linux")]
+        if route_table.is_some() && fwmark.is_none() {
+            bail!("{}", ctx("route-table requires fwmark"));
+        }
+
+        #[cfg(all(target_os = "linux", feature = "reuse_port"))]
+        let force_reuse = self.force_reuse.or(defaults.force_reuse).unwrap_or(false);
+        #[cfg(all(target_os = "linux", feature = "reuse_port"))]
+        let force_reuse_retries = self.force_reuse_retries.or(defaults.force_reuse_retries);
+        #[cfg(all(target_os = "linux", feature = "reuse_port"))]
+        if force_reuse_retries.is_some() && !force_reuse {
+            bail!("{}", ctx("force-reuse-retries requires force-reuse"));
+        }
+
         Ok(TaskConfig {
             mode,
             bind,
             stun,
             keepalive,
             exec,
+            alert,
+            alert_exec,
+            dry_run: self.dry_run.or(defaults.dry_run).unwrap_or(false),
+            verify_peer,
+            ipv6_change: self
+                .ipv6_change
+                .or(defaults.ipv6_change)
+                .map_or(Ipv6ChangePolicy::Report, Into::into),
+            dns,
+            flap_damping: self
+                .flap_damping
+                .or(defaults.flap_damping)
+                .map(Duration::from_secs),
+            port_fallback,
+            port_fallback_check,
             #[cfg(target_os = "linux")]
             iface,
             #[cfg(target_os = "linux")]
-            fwmark: self.fwmark.or(defaults.fwmark),
+            fwmark,
             #[cfg(target_os = "linux")]
-            force_reuse: self.force_reuse.or(defaults.force_reuse).unwrap_or(false),
+            route_table,
+            #[cfg(all(target_os = "linux", feature = "reuse_port"))]
+            force_reuse,
+            #[cfg(all(target_os = "linux", feature = "reuse_port"))]
+            force_reuse_retries,
         })
     }
 }
@@ -220,13 +390,20 @@ impl TaskEntry {
 #[non_exhaustive]
 pub struct MultiConfig {
     pub tasks: HashMap<String, TaskConfig>,
+    pub exit_on: ExitPolicy,
+    /// Exit status when `exit_on` ends the batch.
+    pub exit_code: u8,
 }
 
 impl MultiConfig {
     pub fn load(path: &PathBuf) -> Result<Self> {
         let content = std::fs::read_to_string(path)
             .with_context(|| format!("failed to read {}", path.display()))?;
-        let file: BatchFile = toml::from_str(&content).context("failed to parse config")?;
+        let mut table: toml::Table = toml::from_str(&content).context("failed to parse config")?;
+        for warning in super::schema::migrate(&mut table).context("unsupported config version")? {
+            eprintln!("nyat: {}: {warning}", path.display());
+        }
+        let file: BatchFile = table.try_into().context("failed to parse config")?;
 
         if file.task.is_empty() {
             bail!("no [task.*] entries in {}", path.display());
@@ -246,6 +423,10 @@ impl MultiConfig {
             })
             .collect::<Result<HashMap<_, _>>>()?;
 
-        Ok(Self { tasks: configs })
+        Ok(Self {
+            tasks: configs,
+            exit_on: file.exit_on.unwrap_or_default(),
+            exit_code: file.exit_code.unwrap_or(DEFAULT_EXIT_CODE),
+        })
     }
 }
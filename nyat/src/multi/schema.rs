@@ -0,0 +1,54 @@
+//! Batch config schema versioning.
+//!
+//! The top-level `version` field selects the schema a file was written
+//! against. Older files are upgraded in place, one step at a time, before
+//! they reach the strict (`deny_unknown_fields`) deserializer, so renamed or
+//! restructured keys keep working with a deprecation warning instead of a
+//! hard parse error.
+use anyhow::{Result, bail};
+use toml::{Table, Value};
+
+/// Schema version written by this release.
+pub(crate) const CURRENT: u32 = 1;
+
+/// `MIGRATIONS[n]` upgrades a version `n` table to version `n + 1`.
+const MIGRATIONS: [fn(&mut Table, &mut Vec<String>); CURRENT as usize] = [v0_to_v1];
+
+/// Version 0 is any file predating the `version` field; its layout is
+/// identical to version 1.
+fn v0_to_v1(_: &mut Table, warnings: &mut Vec<String>) {
+    warnings.push(format!(
+        "config has no `version` field, assuming the unversioned schema; \
+         add `version = {CURRENT}` at the top of the file"
+    ));
+}
+
+/// Upgrade `table` to [`CURRENT`] and strip the `version` key.
+///
+/// Returns the deprecation warnings produced along the way.
+pub(super) fn migrate(table: &mut Table) -> Result<Vec<String>> {
+    let version = match table.remove("version") {
+        None => 0,
+        Some(Value::Integer(v)) => match u32::try_from(v) {
+            Ok(v) if v >= 1 => v,
+            _ => bail!("invalid `version` {v}: expected 1..={CURRENT}"),
+        },
+        Some(other) => bail!(
+            "invalid `version`: expected an integer, found {}",
+            other.type_str()
+        ),
+    };
+
+    if version > CURRENT {
+        bail!(
+            "config version {version} is newer than supported ({CURRENT}), \
+             please upgrade nyat"
+        );
+    }
+
+    let mut warnings = Vec::new();
+    for step in &MIGRATIONS[version as usize..] {
+        step(table, &mut warnings);
+    }
+    Ok(warnings)
+}
@@ -0,0 +1,97 @@
+use std::io::Write;
+use std::net::{IpAddr, SocketAddr};
+
+use anyhow::{Context, bail};
+use nyat_core::net::{IpVer, LocalAddr, RemoteAddr};
+use nyat_core::probe::ProbeOptions;
+use tokio::task::JoinSet;
+
+/// Resolved configuration for `nyat probe`.
+pub struct ProbeConfig {
+    pub udp: bool,
+    pub options: ProbeOptions,
+    pub iface: String,
+    pub port: u16,
+    pub family: Option<IpVer>,
+    pub stun_v4: RemoteAddr,
+    pub stun_v6: RemoteAddr,
+}
+
+/// Probe once from every usable address of the interface and print one
+/// mapping line per source address, in interface order.
+pub fn proc(config: ProbeConfig) -> anyhow::Result<()> {
+    let addrs: Vec<IpAddr> = nyat_core::net::interface_addrs(&config.iface)
+        .with_context(|| format!("failed to list addresses of {}", config.iface))?
+        .into_iter()
+        .filter(|ip| match config.family {
+            Some(IpVer::V4) => ip.is_ipv4(),
+            Some(IpVer::V6) => ip.is_ipv6(),
+            None => true,
+        })
+        .collect();
+
+    if addrs.is_empty() {
+        bail!("no usable addresses on {}", config.iface);
+    }
+
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+
+    let results = rt.block_on(async {
+        let mut set = JoinSet::new();
+        for (i, ip) in addrs.into_iter().enumerate() {
+            #[allow(unused_mut)]
+            let mut local = LocalAddr::new(SocketAddr::new(ip, config.port));
+            #[cfg(target_os = "linux")]
+            {
+                local = local.with_iface(config.iface.as_bytes());
+            }
+            let stun = if ip.is_ipv6() {
+                config.stun_v6.clone()
+            } else {
+                config.stun_v4.clone()
+            };
+            let (udp, options) = (config.udp, config.options);
+            set.spawn(async move {
+                let res = if udp {
+                    nyat_core::probe::udp_with(&local, &stun, options).await
+                } else {
+                    nyat_core::probe::tcp_with(&local, &stun, options).await
+                };
+                (i, ip, res)
+            });
+        }
+
+        let mut results = Vec::new();
+        while let Some(r) = set.join_next().await {
+            results.push(r?);
+        }
+        results.sort_by_key(|(i, ..)| *i);
+        Ok::<_, anyhow::Error>(results)
+    })?;
+
+    let mut ok = 0usize;
+    let mut stdout = std::io::stdout().lock();
+    for (_, ip, res) in results {
+        match res {
+            Ok(info) => {
+                ok += 1;
+                writeln!(
+                    stdout,
+                    "{} {} {} {}",
+                    info.pub_addr.ip(),
+                    info.pub_addr.port(),
+                    info.local_addr.ip(),
+                    info.local_addr.port(),
+                )?;
+            }
+            Err(e) => eprintln!("nyat: {ip}: {:#}", anyhow::Error::from(e)),
+        }
+    }
+
+    if ok == 0 {
+        bail!("no address on {} produced a mapping", config.iface);
+    }
+    Ok(())
+}
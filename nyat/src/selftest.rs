@@ -0,0 +1,213 @@
+//! Network-free smoke test: loopback STUN server + HTTP keepalive responder.
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail, ensure};
+use nyat_core::mapper::{Mapper, MapperBuilder, Overflow, channel};
+use nyat_core::net::{LocalAddr, RemoteAddr};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+
+pub(crate) const LOOPBACK: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+const MAGIC_COOKIE: [u8; 4] = 0x2112_A442u32.to_be_bytes();
+const HEADER_SIZE: usize = 20;
+const TEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Build a Binding Success Response carrying `peer` as XOR-MAPPED-ADDRESS.
+///
+/// Returns `None` if `req` is not a Binding Request.
+pub(crate) fn binding_response(req: &[u8], peer: SocketAddr) -> Option<Vec<u8>> {
+    if req.len() < HEADER_SIZE || req[0..2] != [0x00, 0x01] || req[4..8] != MAGIC_COOKIE {
+        return None;
+    }
+    let tx_id = &req[8..20];
+
+    let mut value = vec![0u8, 0];
+    let xport = peer.port() ^ u16::from_be_bytes([MAGIC_COOKIE[0], MAGIC_COOKIE[1]]);
+    match peer.ip() {
+        IpAddr::V4(ip) => {
+            value[1] = 0x01;
+            value.extend_from_slice(&xport.to_be_bytes());
+            value.extend(ip.octets().iter().zip(MAGIC_COOKIE).map(|(a, k)| a ^ k));
+        }
+        IpAddr::V6(ip) => {
+            value[1] = 0x02;
+            value.extend_from_slice(&xport.to_be_bytes());
+            let key = MAGIC_COOKIE.iter().chain(tx_id);
+            value.extend(ip.octets().iter().zip(key).map(|(a, k)| a ^ k));
+        }
+    }
+
+    let mut resp = Vec::with_capacity(HEADER_SIZE + 4 + value.len());
+    resp.extend_from_slice(&0x0101u16.to_be_bytes());
+    resp.extend_from_slice(&((4 + value.len()) as u16).to_be_bytes());
+    resp.extend_from_slice(&MAGIC_COOKIE);
+    resp.extend_from_slice(tx_id);
+    resp.extend_from_slice(&0x0020u16.to_be_bytes());
+    resp.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    resp.extend_from_slice(&value);
+    Some(resp)
+}
+
+/// UDP STUN server; non-STUN datagrams are counted as keepalives.
+async fn udp_stun(socket: UdpSocket, keepalives: Arc<AtomicUsize>) -> Result<()> {
+    let mut buf = [0u8; 2048];
+    loop {
+        let (len, peer) = socket.recv_from(&mut buf).await?;
+        match binding_response(&buf[..len], peer) {
+            Some(resp) => {
+                socket.send_to(&resp, peer).await?;
+            }
+            None => {
+                keepalives.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// TCP STUN server: answers one Binding Request per connection.
+async fn tcp_stun(listener: TcpListener) -> Result<()> {
+    loop {
+        let (mut stream, peer) = listener.accept().await?;
+        tokio::spawn(async move {
+            let mut header = [0u8; HEADER_SIZE];
+            stream.read_exact(&mut header).await?;
+            let body_len = u16::from_be_bytes([header[2], header[3]]) as usize;
+            let mut body = vec![0u8; body_len];
+            stream.read_exact(&mut body).await?;
+            if let Some(resp) = binding_response(&header, peer) {
+                stream.write_all(&resp).await?;
+            }
+            Ok::<_, std::io::Error>(())
+        });
+    }
+}
+
+/// Minimal HTTP/1.1 server answering every request head with `200 OK`.
+async fn http_keepalive(listener: TcpListener, requests: Arc<AtomicUsize>) -> Result<()> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let requests = requests.clone();
+        tokio::spawn(serve_http(stream, requests));
+    }
+}
+
+async fn serve_http(mut stream: TcpStream, requests: Arc<AtomicUsize>) -> std::io::Result<()> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 1024];
+    loop {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        while let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            buf.drain(..end + 4);
+            requests.fetch_add(1, Ordering::Relaxed);
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .await?;
+        }
+    }
+}
+
+/// Wait until the mapper reports a mapping and `keepalives` has moved,
+/// checking the reported mapping is the loopback address itself and that
+/// it answers peer verification.
+async fn check(mapper: Mapper, keepalives: Arc<AtomicUsize>) -> Result<SocketAddr> {
+    let (mut handler, mut rx) = channel(NonZeroUsize::MIN, Overflow::Coalesce);
+    let udp = matches!(mapper, Mapper::Udp(_));
+
+    let verify = async {
+        let info = rx.recv().await.context("mapper stopped")?;
+        ensure!(
+            info.pub_addr == info.local_addr,
+            "reported {} for local {}",
+            info.pub_addr,
+            info.local_addr
+        );
+        while keepalives.load(Ordering::Relaxed) == 0 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        let local = LocalAddr::new(SocketAddr::new(info.local_addr.ip(), 0));
+        let peer = RemoteAddr::from_addr(info.pub_addr);
+        if udp {
+            nyat_core::verify::udp(&local, &peer).await
+        } else {
+            nyat_core::verify::tcp(&local, &peer).await
+        }
+        .context("peer verification")?;
+        Ok(info.pub_addr)
+    };
+
+    tokio::time::timeout(TEST_TIMEOUT, async {
+        tokio::select! {
+            res = mapper.run(&mut handler) => match res {
+                Ok(()) => bail!("mapper exited"),
+                Err(e) => Err(anyhow::Error::from(e)),
+            },
+            res = verify => res,
+        }
+    })
+    .await
+    .context("timed out")?
+}
+
+pub fn proc() -> Result<()> {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+
+    let failed = rt.block_on(async {
+        let udp_socket = UdpSocket::bind(LOOPBACK).await?;
+        let tcp_listener = TcpListener::bind(LOOPBACK).await?;
+        let http_listener = TcpListener::bind(LOOPBACK).await?;
+        let udp_addr = udp_socket.local_addr()?;
+        let tcp_addr = tcp_listener.local_addr()?;
+        let http_addr = http_listener.local_addr()?;
+
+        let udp_keepalives = Arc::new(AtomicUsize::new(0));
+        let http_requests = Arc::new(AtomicUsize::new(0));
+        tokio::spawn(udp_stun(udp_socket, udp_keepalives.clone()));
+        tokio::spawn(tcp_stun(tcp_listener));
+        tokio::spawn(http_keepalive(http_listener, http_requests.clone()));
+
+        let tcp = MapperBuilder::new_tcp(
+            LocalAddr::new(LOOPBACK),
+            RemoteAddr::from_addr(tcp_addr),
+            RemoteAddr::from_addr(http_addr),
+        )
+        .interval(Duration::from_millis(200))
+        .verify_responder(true)
+        .build();
+
+        let udp = MapperBuilder::new_udp(LocalAddr::new(LOOPBACK), RemoteAddr::from_addr(udp_addr))
+            .interval(Duration::from_millis(200))
+            .check_per_tick(NonZeroUsize::new(2).unwrap())
+            .verify_responder(true)
+            .build();
+
+        let mut failed = 0;
+        for (name, mapper, counter) in [
+            ("tcp", Mapper::from(tcp), http_requests),
+            ("udp", Mapper::from(udp), udp_keepalives),
+        ] {
+            match check(mapper, counter).await {
+                Ok(addr) => println!("{name}: pass ({addr})"),
+                Err(e) => {
+                    failed += 1;
+                    println!("{name}: FAIL: {e:#}");
+                }
+            }
+        }
+        Ok::<_, anyhow::Error>(failed)
+    })?;
+
+    if failed > 0 {
+        bail!("{failed} selftest(s) failed");
+    }
+    Ok(())
+}
@@ -1,7 +1,7 @@
 use std::io::Write;
 use std::time::Duration;
 
-use nyat_core::mapper::{MappingHandler, MappingInfo};
+use nyat_core::mapper::{MappingEvent, MappingHandler, MappingInfo};
 
 use crate::config::TaskConfig;
 use crate::hooks::Hooks;
@@ -33,26 +33,54 @@ impl MappingHandler for Handler {
             std::process::exit(0);
         }
     }
+
+    fn on_event(&mut self, event: &MappingEvent<'_>) {
+        match *event {
+            MappingEvent::AddressChanged(info) => self.on_change(info),
+            MappingEvent::PortSelected {
+                preferred,
+                selected,
+            } => crate::hooks::report_port_selected("nyat:", preferred, selected),
+            _ => {}
+        }
+    }
 }
 
 pub fn proc(mut config: TaskConfig) -> anyhow::Result<()> {
-    let mut handler = Handler::new(Hooks::new(config.exec.take()));
+    let hooks =
+        Hooks::new(config.exec.take()).with_alerts(config.alert, config.alert_exec.take(), "nyat:");
+    let mut handler = Handler::new(hooks);
+    let dry_run = config.dry_run;
+    let verify_peer = config.verify_peer.clone();
+    let local = config.local();
     let mapper = config.into_mapper();
 
     let rt = tokio::runtime::Builder::new_current_thread()
         .enable_all()
         .build()?;
 
+    if dry_run {
+        let plan = rt.block_on(mapper.dry_run())?;
+        println!("{plan}");
+        return Ok(());
+    }
+
     rt.block_on(async {
-        loop {
-            match mapper.run(&mut handler).await {
-                Ok(()) => {}
-                Err(e) if e.is_recoverable() => {
-                    eprintln!("nyat: {:#}, retrying...", anyhow::Error::from(e));
-                    tokio::time::sleep(Duration::from_secs(5)).await;
+        let run = async {
+            loop {
+                match mapper.run(&mut handler).await {
+                    Ok(()) => {}
+                    Err(e) if e.is_recoverable() => {
+                        eprintln!("nyat: {:#}, retrying...", anyhow::Error::from(e));
+                        tokio::time::sleep(Duration::from_secs(5)).await;
+                    }
+                    Err(e) => return Err(anyhow::Error::from(e)),
                 }
-                Err(e) => return Err(anyhow::Error::from(e)),
             }
+        };
+        tokio::select! {
+            res = run => res,
+            never = crate::verify::watch("nyat:", verify_peer, local, &mapper) => match never {},
         }
     })
 }
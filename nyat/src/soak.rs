@@ -0,0 +1,546 @@
+//! Robustness soak: hostile STUN responses through the parser and both
+//! mappers.
+//!
+//! The parser phase feeds every corpus file, plus valid and damaged
+//! responses, to `parse_response`. The mapper phase runs a TCP and a UDP
+//! mapper against loopback servers answering with the same kind of junk —
+//! dropped, truncated, bit-flipped, stale and error responses, keepalive
+//! connections closed at random — then turns the servers honest and
+//! requires both mappers to report the real mapping again.
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result, bail};
+use nyat_core::mapper::{
+    FlapDamping, Mapper, MapperBuilder, MappingEvent, MappingHandler, MappingInfo,
+};
+use nyat_core::net::{LocalAddr, RemoteAddr};
+use nyat_core::stun::{self, HEADER_SIZE, TransactionId};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::time::Instant;
+
+use crate::selftest::{LOOPBACK, binding_response};
+
+const MAGIC_COOKIE: [u8; 4] = 0x2112_A442u32.to_be_bytes();
+/// Generated responses in the parser phase, each also parsed damaged.
+const PARSE_ROUNDS: usize = 200_000;
+/// Keepalive interval of the soaked mappers.
+const INTERVAL: Duration = Duration::from_millis(200);
+/// STUN timeout of the soaked mappers, so dropped answers cost little.
+const STUN_TIMEOUT: Duration = Duration::from_millis(500);
+/// Wait before the soaked mappers rebuild a session, instead of the
+/// default 5 s: flap damping after every teardown, settling this long.
+const REBUILD_DELAY: Duration = Duration::from_millis(50);
+/// Probes each mapper must finish per second of the hostile phase.
+const MIN_PROBE_RATE: u64 = 1;
+/// How long the mappers get to report the real mapping once the servers
+/// are honest; covers a rebuild delay and a STUN timeout.
+const RECOVERY_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct SoakConfig {
+    pub duration: Duration,
+    pub seed: Option<u64>,
+    pub corpus: Vec<PathBuf>,
+}
+
+thread_local! {
+    /// Last STUN input handed to the parser, printed if it panics.
+    static LAST_INPUT: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+fn remember(input: &[u8]) {
+    LAST_INPUT.with_borrow_mut(|last| {
+        last.clear();
+        last.extend_from_slice(input);
+    });
+}
+
+/// xorshift64*: reproducible from `--seed`, no dependency.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Uniform in `0..n`; `0` if `n` is `0`.
+    fn below(&mut self, n: usize) -> usize {
+        if n == 0 {
+            0
+        } else {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    fn bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.next() as u8).collect()
+    }
+
+    fn addr(&mut self) -> SocketAddr {
+        let ip = if self.next() & 1 == 0 {
+            IpAddr::V4(Ipv4Addr::from(self.next() as u32))
+        } else {
+            IpAddr::V6(Ipv6Addr::from(
+                u128::from(self.next()) << 64 | u128::from(self.next()),
+            ))
+        };
+        SocketAddr::new(ip, self.next() as u16)
+    }
+}
+
+/// Header of a Binding Request with `tx_id`, enough for [`binding_response`].
+fn request_header(tx_id: &TransactionId) -> [u8; HEADER_SIZE] {
+    let mut req = [0u8; HEADER_SIZE];
+    req[1] = 0x01;
+    req[4..8].copy_from_slice(&MAGIC_COOKIE);
+    req[8..].copy_from_slice(tx_id);
+    req
+}
+
+/// Binding Error Response to `req` (a full header) with `code`.
+fn error_response(req: &[u8], code: u16) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(HEADER_SIZE + 8);
+    msg.extend_from_slice(&0x0111u16.to_be_bytes());
+    msg.extend_from_slice(&8u16.to_be_bytes());
+    msg.extend_from_slice(&req[4..HEADER_SIZE]);
+    msg.extend_from_slice(&0x0009u16.to_be_bytes());
+    msg.extend_from_slice(&4u16.to_be_bytes());
+    msg.extend_from_slice(&[0, 0, (code / 100 % 8) as u8, (code % 100) as u8]);
+    msg
+}
+
+/// Damage `msg` the way broken middleboxes do.
+fn mutate(msg: &mut Vec<u8>, rng: &mut Rng) {
+    match rng.below(5) {
+        0 if !msg.is_empty() => {
+            for _ in 0..=rng.below(8) {
+                let i = rng.below(msg.len());
+                msg[i] ^= 1 << rng.below(8);
+            }
+        }
+        1 => msg.truncate(rng.below(msg.len())),
+        2 if msg.len() >= 4 => msg[2..4].copy_from_slice(&(rng.next() as u16).to_be_bytes()),
+        3 => {
+            // an attribute whose length may lie
+            let attr_type = rng.next() as u16;
+            let claimed = rng.below(128) as u16;
+            let len = rng.below(64);
+            msg.extend_from_slice(&attr_type.to_be_bytes());
+            msg.extend_from_slice(&claimed.to_be_bytes());
+            msg.extend(rng.bytes(len));
+        }
+        _ => {
+            // two messages in one read
+            let copy = msg.clone();
+            msg.extend(copy);
+        }
+    }
+}
+
+/// Responder shared by the servers: hostile until [`honest`](Self::honest).
+struct Junk {
+    corpus: Vec<Vec<u8>>,
+    rng: Mutex<Rng>,
+    honest: AtomicBool,
+}
+
+impl Junk {
+    fn honest(&self) -> bool {
+        self.honest.load(Ordering::Relaxed)
+    }
+
+    /// One chance in `n`.
+    fn chance(&self, n: usize) -> bool {
+        let mut rng = self.rng.lock().unwrap_or_else(PoisonError::into_inner);
+        rng.below(n) == 0
+    }
+
+    /// Messages answering the Binding Request `req` from `peer`, in order.
+    fn answers(&self, req: &[u8], peer: SocketAddr) -> Vec<Vec<u8>> {
+        let Some(valid) = binding_response(req, peer) else {
+            return Vec::new();
+        };
+        if self.honest() {
+            return vec![valid];
+        }
+        let mut rng = self.rng.lock().unwrap_or_else(PoisonError::into_inner);
+        let answers = match rng.below(8) {
+            0 => Vec::new(),
+            1 => vec![valid],
+            2 => {
+                let mut msg = valid;
+                mutate(&mut msg, &mut rng);
+                vec![msg]
+            }
+            3 => vec![self.replay(req, &mut rng)],
+            4 => {
+                let len = rng.below(4 * HEADER_SIZE);
+                vec![rng.bytes(len)]
+            }
+            5 => {
+                // late answer to an earlier request, then the real one
+                let mut stale = valid.clone();
+                stale[8] ^= 0xff;
+                vec![stale, valid]
+            }
+            6 => {
+                let mut msg = valid.clone();
+                mutate(&mut msg, &mut rng);
+                vec![msg, valid]
+            }
+            _ => vec![error_response(req, rng.next() as u16)],
+        };
+        if let Some(last) = answers.last() {
+            remember(last);
+        }
+        answers
+    }
+
+    /// A corpus entry, or random bytes without a corpus, carrying `req`'s
+    /// transaction ID so that it reaches attribute parsing.
+    fn replay(&self, req: &[u8], rng: &mut Rng) -> Vec<u8> {
+        let mut msg = if self.corpus.is_empty() {
+            let len = rng.below(128);
+            rng.bytes(len)
+        } else {
+            self.corpus[rng.below(self.corpus.len())].clone()
+        };
+        if let Some(tx_id) = msg.get_mut(8..HEADER_SIZE) {
+            tx_id.copy_from_slice(&req[8..HEADER_SIZE]);
+        }
+        msg
+    }
+}
+
+async fn udp_server(socket: UdpSocket, junk: Arc<Junk>) -> Result<()> {
+    let mut buf = [0u8; 2048];
+    loop {
+        let (len, peer) = socket.recv_from(&mut buf).await?;
+        for answer in junk.answers(&buf[..len], peer) {
+            socket.send_to(&answer, peer).await?;
+        }
+    }
+}
+
+/// TCP STUN server: answers one Binding Request per connection, then
+/// closes it so that a partial answer fails fast.
+async fn tcp_server(listener: TcpListener, junk: Arc<Junk>) -> Result<()> {
+    loop {
+        let (mut stream, peer) = listener.accept().await?;
+        let junk = junk.clone();
+        tokio::spawn(async move {
+            let mut header = [0u8; HEADER_SIZE];
+            stream.read_exact(&mut header).await?;
+            let body_len = u16::from_be_bytes([header[2], header[3]]) as usize;
+            let mut body = vec![0u8; body_len];
+            stream.read_exact(&mut body).await?;
+            for answer in junk.answers(&header, peer) {
+                stream.write_all(&answer).await?;
+            }
+            Ok::<_, std::io::Error>(())
+        });
+    }
+}
+
+async fn http_server(listener: TcpListener, junk: Arc<Junk>) -> Result<()> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tokio::spawn(serve_http(stream, junk.clone()));
+    }
+}
+
+/// `200 OK` to every request head. Connections opened while hostile are
+/// dropped at random, and for good once the servers turn honest, so the TCP
+/// mapper rebuilds its session and probes again.
+async fn serve_http(mut stream: TcpStream, junk: Arc<Junk>) -> std::io::Result<()> {
+    let hostile = !junk.honest();
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 1024];
+    loop {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        while let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            buf.drain(..end + 4);
+            if hostile && (junk.honest() || junk.chance(4)) {
+                return Ok(());
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .await?;
+        }
+    }
+}
+
+/// Parser outcomes by error message.
+#[derive(Default)]
+struct ParseTally {
+    inputs: usize,
+    ok: usize,
+    errors: BTreeMap<String, usize>,
+}
+
+impl ParseTally {
+    /// Parse `data` with the transaction ID it carries, as the fuzz target
+    /// does.
+    fn parse(&mut self, data: &[u8]) -> Result<SocketAddr, stun::Error> {
+        remember(data);
+        let mut tx_id = TransactionId::default();
+        if let Some(id) = data.get(8..HEADER_SIZE) {
+            tx_id.copy_from_slice(id);
+        }
+        let _ = stun::message_len(data);
+        let res = stun::parse_response(data, &tx_id);
+        self.inputs += 1;
+        match res {
+            Ok(_) => self.ok += 1,
+            Err(e) => *self.errors.entry(e.to_string()).or_default() += 1,
+        }
+        res
+    }
+}
+
+fn parse_phase(corpus: &[Vec<u8>], rng: &mut Rng) -> Result<ParseTally> {
+    let mut tally = ParseTally::default();
+    for input in corpus {
+        let _ = tally.parse(input);
+    }
+    for _ in 0..PARSE_ROUNDS {
+        let peer = rng.addr();
+        let tx_id: TransactionId = rng.bytes(12).try_into().unwrap();
+        let mut msg = binding_response(&request_header(&tx_id), peer).unwrap();
+        match tally.parse(&msg) {
+            Ok(addr) if addr == peer => {}
+            res => bail!("response mapping {peer} parsed as {res:?}"),
+        }
+        mutate(&mut msg, rng);
+        let _ = tally.parse(&msg);
+    }
+    Ok(tally)
+}
+
+/// Mapper events, and why the mapper exited each time it was restarted.
+struct Tally {
+    junk: Arc<Junk>,
+    probes_ok: usize,
+    probes_failed: usize,
+    changes: usize,
+    reconnects: usize,
+    exits: BTreeMap<String, usize>,
+    /// Set once an honest probe reported the real mapping.
+    recovered: Arc<AtomicBool>,
+}
+
+impl MappingHandler for Tally {
+    fn on_change(&mut self, _info: MappingInfo) {
+        self.changes += 1;
+    }
+
+    fn on_event(&mut self, event: &MappingEvent<'_>) {
+        match *event {
+            MappingEvent::ProbeSucceeded(info) => {
+                self.probes_ok += 1;
+                if self.junk.honest() && info.pub_addr == info.local_addr {
+                    self.recovered.store(true, Ordering::Relaxed);
+                }
+            }
+            MappingEvent::ProbeFailed(_) => self.probes_failed += 1,
+            MappingEvent::Reconnect => self.reconnects += 1,
+            MappingEvent::AddressChanged(info) => self.on_change(info),
+            _ => {}
+        }
+    }
+}
+
+/// Run mappers from `build` until one recovers, restarting it whenever it
+/// gives up, or until `deadline`.
+async fn soak(build: impl Fn() -> Mapper, junk: Arc<Junk>, deadline: Instant) -> Tally {
+    let recovered = Arc::new(AtomicBool::new(false));
+    let mut tally = Tally {
+        junk,
+        probes_ok: 0,
+        probes_failed: 0,
+        changes: 0,
+        reconnects: 0,
+        exits: BTreeMap::new(),
+        recovered: recovered.clone(),
+    };
+    let done = async {
+        while !recovered.load(Ordering::Relaxed) {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    };
+    tokio::pin!(done);
+    loop {
+        let mapper = build();
+        tokio::select! {
+            res = mapper.run(&mut tally) => {
+                let reason = match res {
+                    Ok(()) => "exited".to_owned(),
+                    Err(e) => format!("{:#}", anyhow::Error::from(e)),
+                };
+                *tally.exits.entry(reason).or_default() += 1;
+            }
+            () = &mut done => break,
+            () = tokio::time::sleep_until(deadline) => break,
+        }
+    }
+    tally
+}
+
+/// Print `tally`. Returns whether the mapper recovered after at least
+/// `min_probes` probes.
+fn report(name: &str, tally: &Tally, min_probes: usize) -> bool {
+    let recovered = tally.recovered.load(Ordering::Relaxed);
+    let probes = tally.probes_ok + tally.probes_failed;
+    println!(
+        "{name}: {} probes ok, {} failed, {} address changes, {} reconnects; {}",
+        tally.probes_ok,
+        tally.probes_failed,
+        tally.changes,
+        tally.reconnects,
+        match (recovered, probes >= min_probes) {
+            (false, _) => "DID NOT RECOVER".to_owned(),
+            (true, false) => format!("TOO FEW PROBES (expected {min_probes})"),
+            (true, true) => "recovered".to_owned(),
+        }
+    );
+    for (reason, n) in &tally.exits {
+        println!("  gave up {n}x: {reason}");
+    }
+    recovered && probes >= min_probes
+}
+
+/// Files of `paths`, directories expanded one level, in a stable order.
+fn load_corpus(paths: &[PathBuf]) -> Result<Vec<Vec<u8>>> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let entries = std::fs::read_dir(path)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            for entry in entries {
+                let file = entry?.path();
+                if file.is_file() {
+                    files.push(file);
+                }
+            }
+        } else {
+            files.push(path.clone());
+        }
+    }
+    files.sort();
+    files
+        .iter()
+        .map(|f| std::fs::read(f).with_context(|| format!("failed to read {}", f.display())))
+        .collect()
+}
+
+pub fn proc(config: SoakConfig) -> Result<()> {
+    let seed = config.seed.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64)
+    });
+    let corpus = load_corpus(&config.corpus)?;
+    println!("seed {seed}, {} corpus input(s)", corpus.len());
+
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        LAST_INPUT.with_borrow(|input| {
+            let hex: String = input.iter().map(|b| format!("{b:02x}")).collect();
+            eprintln!("soak: seed {seed}, last STUN input: {hex}");
+        });
+    }));
+
+    let mut rng = Rng::new(seed);
+    let parsed = parse_phase(&corpus, &mut rng)?;
+    println!("parser: {} inputs, {} ok", parsed.inputs, parsed.ok);
+    for (error, n) in &parsed.errors {
+        println!("  {n}x {error}");
+    }
+
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+
+    let failed = rt.block_on(async {
+        let udp_socket = UdpSocket::bind(LOOPBACK).await?;
+        let tcp_listener = TcpListener::bind(LOOPBACK).await?;
+        let http_listener = TcpListener::bind(LOOPBACK).await?;
+        let udp_addr = udp_socket.local_addr()?;
+        let tcp_addr = tcp_listener.local_addr()?;
+        let http_addr = http_listener.local_addr()?;
+
+        let junk = Arc::new(Junk {
+            corpus,
+            rng: Mutex::new(rng),
+            honest: AtomicBool::new(false),
+        });
+        tokio::spawn(udp_server(udp_socket, junk.clone()));
+        tokio::spawn(tcp_server(tcp_listener, junk.clone()));
+        tokio::spawn(http_server(http_listener, junk.clone()));
+
+        let rebuild = FlapDamping::new(REBUILD_DELAY).threshold(1, INTERVAL);
+        let tcp = || {
+            MapperBuilder::new_tcp(
+                LocalAddr::new(LOOPBACK),
+                RemoteAddr::from_addr(tcp_addr),
+                RemoteAddr::from_addr(http_addr),
+            )
+            .interval(INTERVAL)
+            .stun_timeout(STUN_TIMEOUT)
+            .flap_damping(rebuild)
+            .build()
+            .into()
+        };
+        let udp = || {
+            MapperBuilder::new_udp(LocalAddr::new(LOOPBACK), RemoteAddr::from_addr(udp_addr))
+                .interval(INTERVAL)
+                .stun_timeout(STUN_TIMEOUT)
+                .flap_damping(rebuild)
+                .check_per_tick(NonZeroUsize::MIN)
+                .build()
+                .into()
+        };
+
+        let min_probes = (config.duration.as_secs() * MIN_PROBE_RATE) as usize;
+        let honest_at = Instant::now() + config.duration;
+        let deadline = honest_at + RECOVERY_TIMEOUT;
+        let (tcp, udp, ()) = tokio::join!(
+            soak(tcp, junk.clone(), deadline),
+            soak(udp, junk.clone(), deadline),
+            async {
+                tokio::time::sleep_until(honest_at).await;
+                junk.honest.store(true, Ordering::Relaxed);
+            }
+        );
+
+        let failed = [("tcp", &tcp), ("udp", &udp)]
+            .into_iter()
+            .filter(|(name, tally)| !report(name, tally, min_probes))
+            .count();
+        Ok::<_, anyhow::Error>(failed)
+    })?;
+
+    if failed > 0 {
+        bail!("{failed} mapper(s) failed the soak");
+    }
+    Ok(())
+}
@@ -0,0 +1,347 @@
+//! `nyat support-bundle`: collect diagnostics into a tar archive.
+mod tar;
+
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use nyat_core::mapper::MappingInfo;
+use nyat_core::net::{LocalAddr, RemoteAddr};
+use tokio::time::timeout;
+
+use crate::multi::MultiConfig;
+use tar::TarWriter;
+
+const ROOT: &str = "nyat-support";
+/// Only the tail of `--log` is kept.
+const LOG_LIMIT: u64 = 256 * 1024;
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+const REDACTED: &str = "<redacted>";
+
+/// Resolved configuration for `nyat support-bundle`.
+pub struct BundleConfig {
+    pub config: Option<PathBuf>,
+    pub stun: Vec<RemoteAddr>,
+    pub log: Option<PathBuf>,
+    pub output: Option<PathBuf>,
+}
+
+/// A STUN server to probe and where the probe is sent from.
+struct Target {
+    stun: RemoteAddr,
+    bind: SocketAddr,
+    local: LocalAddr,
+    origin: String,
+}
+
+pub fn proc(config: BundleConfig) -> Result<()> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let output = config
+        .output
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(format!("nyat-support-{now}.tar")));
+
+    let mut entries: Vec<(&str, String)> = vec![
+        ("README.txt", readme(&config)),
+        ("platform.txt", platform()),
+        ("env.txt", env()),
+    ];
+
+    let mut targets = Vec::new();
+    if let Some(ref path) = config.config {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        entries.push(("config.toml", sanitize(&content)));
+        entries.push(("config-check.txt", check(path, &mut targets)));
+    }
+    for stun in &config.stun {
+        let bind = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
+        targets.push(Target {
+            stun: stun.clone(),
+            bind,
+            local: LocalAddr::new(bind),
+            origin: "--stun".into(),
+        });
+    }
+    if !targets.is_empty() {
+        eprintln!("nyat: probing {} STUN server(s)...", targets.len());
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        entries.push(("stun.txt", rt.block_on(stun_dump(&targets))));
+    }
+    if let Some(ref path) = config.log {
+        entries.push((
+            "log.txt",
+            log_tail(path).with_context(|| format!("failed to read {}", path.display()))?,
+        ));
+    }
+
+    let file =
+        File::create(&output).with_context(|| format!("failed to create {}", output.display()))?;
+    let mut tar = TarWriter::new(BufWriter::new(file), now);
+    for (name, content) in &entries {
+        tar.append(&format!("{ROOT}/{name}"), content.as_bytes())?;
+    }
+    tar.finish()?;
+
+    println!("{}", output.display());
+    Ok(())
+}
+
+fn readme(config: &BundleConfig) -> String {
+    let mut s = String::from(
+        "nyat support bundle\n\
+         \n\
+         platform.txt      nyat version, features, OS and NAT-related sysctls\n\
+         env.txt           NYAT_* environment variables (commands redacted)\n",
+    );
+    if config.config.is_some() {
+        s.push_str(
+            "config.toml       batch config, `exec` / `alert-exec` redacted\n\
+             config-check.txt  result of loading the config\n",
+        );
+    }
+    if config.config.is_some() || !config.stun.is_empty() {
+        s.push_str("stun.txt          system DNS and one-shot UDP/TCP STUN probes per server\n");
+    }
+    if config.log.is_some() {
+        s.push_str("log.txt           tail of the supplied log\n");
+    }
+    s.push_str(
+        "\nnyat keeps no event log or status of its own; without --log, paste\n\
+         the stderr output of the failing task into the report.\n\
+         STUN results contain public addresses.\n",
+    );
+    s
+}
+
+fn platform() -> String {
+    let mut features = Vec::new();
+    for (name, enabled) in [
+        ("batch", cfg!(feature = "batch")),
+        ("migrate", cfg!(feature = "migrate")),
+        ("probe", cfg!(feature = "probe")),
+        ("reuse_port", cfg!(feature = "reuse_port")),
+        ("selftest", cfg!(feature = "selftest")),
+        ("soak", cfg!(feature = "soak")),
+        ("support-bundle", cfg!(feature = "support-bundle")),
+    ] {
+        if enabled {
+            features.push(name);
+        }
+    }
+
+    let mut s = String::new();
+    let _ = writeln!(s, "nyat {}", env!("CARGO_PKG_VERSION"));
+    let _ = writeln!(s, "features: {}", features.join(" "));
+    let _ = writeln!(
+        s,
+        "target: {} {} ({})",
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        std::env::consts::FAMILY
+    );
+
+    #[cfg(target_os = "linux")]
+    {
+        let read = |path: &str| std::fs::read_to_string(path).ok();
+        if let Some(release) = read("/proc/sys/kernel/osrelease") {
+            let _ = writeln!(s, "kernel: {}", release.trim());
+        }
+        if let Some(os) = read("/etc/os-release").and_then(|c| {
+            c.lines()
+                .find_map(|l| l.strip_prefix("PRETTY_NAME="))
+                .map(|v| v.trim_matches('"').to_owned())
+        }) {
+            let _ = writeln!(s, "os: {os}");
+        }
+        for key in [
+            "net/ipv4/ip_local_port_range",
+            "net/ipv4/tcp_keepalive_time",
+            "net/netfilter/nf_conntrack_udp_timeout",
+            "net/netfilter/nf_conntrack_udp_timeout_stream",
+            "net/netfilter/nf_conntrack_tcp_timeout_established",
+        ] {
+            if let Some(value) = read(&format!("/proc/sys/{key}")) {
+                let _ = writeln!(s, "{}: {}", key.replace('/', "."), value.trim());
+            }
+        }
+    }
+    s
+}
+
+fn env() -> String {
+    let mut vars: Vec<_> = std::env::vars_os()
+        .filter_map(|(k, v)| {
+            let k = k.into_string().ok()?;
+            k.starts_with("NYAT_").then(|| {
+                let v = if k == "NYAT_EXEC" || k == "NYAT_ALERT_EXEC" {
+                    REDACTED.into()
+                } else {
+                    v.to_string_lossy().into_owned()
+                };
+                (k, v)
+            })
+        })
+        .collect();
+    vars.sort();
+    vars.into_iter()
+        .map(|(k, v)| format!("{k}={v}\n"))
+        .collect()
+}
+
+/// Redact `exec` and `alert-exec` commands, which may carry API tokens.
+///
+/// Unparseable files are left out; `config-check.txt` has the error.
+fn sanitize(content: &str) -> String {
+    let Ok(mut table) = toml::from_str::<toml::Table>(content) else {
+        return "# not valid TOML, omitted\n".into();
+    };
+    let redact = |t: &mut toml::Table| {
+        for key in ["exec", "alert-exec"] {
+            if let Some(exec) = t.get_mut(key) {
+                *exec = toml::Value::String(REDACTED.into());
+            }
+        }
+    };
+    if let Some(toml::Value::Table(default)) = table.get_mut("default") {
+        redact(default);
+    }
+    if let Some(toml::Value::Table(tasks)) = table.get_mut("task") {
+        for (_, task) in tasks.iter_mut() {
+            if let toml::Value::Table(task) = task {
+                redact(task);
+            }
+        }
+    }
+    toml::to_string(&table).unwrap_or_default()
+}
+
+/// Load the batch config as `nyat batch` would, collecting each task's
+/// STUN server.
+fn check(path: &PathBuf, targets: &mut Vec<Target>) -> String {
+    let config = match MultiConfig::load(path) {
+        Ok(config) => config,
+        Err(e) => return format!("error: {e:#}\n"),
+    };
+
+    let mut names: Vec<_> = config.tasks.keys().collect();
+    names.sort();
+    let mut s = format!("ok: {} task(s)\n", names.len());
+    for name in names {
+        let task = &config.tasks[name];
+        let mode = match task.mode {
+            crate::config::RunMode::Tcp { .. } => "tcp",
+            crate::config::RunMode::Udp { .. } => "udp",
+        };
+        let _ = writeln!(s, "{name}: {mode} bind {} stun {}", task.bind, task.stun);
+        // the bundle only reports the rule; without it, probes below leave
+        // through the main table
+        #[cfg(target_os = "linux")]
+        if let (Some(table), Some(mark)) = (task.route_table, task.fwmark) {
+            let _ = writeln!(
+                s,
+                "{name}: route-table {table}: `ip rule fwmark {mark} lookup {table}` not installed by support-bundle"
+            );
+        }
+
+        let bind = SocketAddr::new(task.bind.ip(), 0);
+        let stun = task.stun.to_string();
+        if targets
+            .iter()
+            .any(|t| t.stun.to_string() == stun && t.bind == bind)
+        {
+            continue;
+        }
+        #[allow(unused_mut)]
+        let mut local = LocalAddr::new(bind).with_dns_servers(task.dns.iter().copied());
+        #[cfg(target_os = "linux")]
+        {
+            if let Some(fmark) = task.fwmark {
+                local = local.with_fmark(fmark);
+            }
+            if let Some(ref iface) = task.iface {
+                local = local.with_iface(iface.as_bytes());
+            }
+            if let Some(table) = task.route_table {
+                local = local.with_route_table(table);
+            }
+        }
+        targets.push(Target {
+            stun: task.stun.clone(),
+            bind,
+            local,
+            origin: format!("task '{name}'"),
+        });
+    }
+    s
+}
+
+async fn stun_dump(targets: &[Target]) -> String {
+    let mut s = String::new();
+    for target in targets {
+        let _ = writeln!(
+            s,
+            "# {} from {} ({})",
+            target.stun, target.bind, target.origin
+        );
+        match tokio::net::lookup_host(target.stun.to_string()).await {
+            Ok(addrs) => {
+                let addrs: Vec<_> = addrs.map(|a| a.to_string()).collect();
+                let _ = writeln!(s, "system dns: {}", addrs.join(" "));
+            }
+            Err(e) => {
+                let _ = writeln!(s, "system dns: error: {e}");
+            }
+        }
+
+        // two UDP probes from different ports hint at port allocation
+        for label in ["udp #1", "udp #2", "tcp"] {
+            let start = Instant::now();
+            let probe = async {
+                if label == "tcp" {
+                    nyat_core::probe::tcp(&target.local, &target.stun).await
+                } else {
+                    nyat_core::probe::udp(&target.local, &target.stun).await
+                }
+            };
+            let res = timeout(PROBE_TIMEOUT, probe).await;
+            let elapsed = start.elapsed().as_millis();
+            let _ = match res {
+                Ok(Ok(MappingInfo {
+                    pub_addr,
+                    local_addr,
+                    ..
+                })) => writeln!(s, "{label}: {pub_addr} from {local_addr} in {elapsed} ms"),
+                Ok(Err(e)) => writeln!(
+                    s,
+                    "{label}: error after {elapsed} ms: {:#}",
+                    anyhow::Error::from(e)
+                ),
+                Err(_) => writeln!(s, "{label}: timed out after {elapsed} ms"),
+            };
+        }
+        s.push('\n');
+    }
+    s
+}
+
+/// The last [`LOG_LIMIT`] bytes of `path`.
+fn log_tail(path: &PathBuf) -> std::io::Result<String> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    if len > LOG_LIMIT {
+        file.seek(SeekFrom::Start(len - LOG_LIMIT))?;
+    }
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf)?;
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
@@ -0,0 +1,60 @@
+//! Minimal ustar writer: regular files only, no compression.
+use std::io::{self, Write};
+
+const BLOCK: usize = 512;
+
+pub struct TarWriter<W: Write> {
+    inner: W,
+    mtime: u64,
+}
+
+impl<W: Write> TarWriter<W> {
+    /// Every entry is stamped with `mtime` (seconds since the epoch).
+    pub const fn new(inner: W, mtime: u64) -> Self {
+        Self { inner, mtime }
+    }
+
+    /// Append a regular file (mode 0644).
+    pub fn append(&mut self, path: &str, data: &[u8]) -> io::Result<()> {
+        if path.len() > 100 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("tar entry name too long: {path}"),
+            ));
+        }
+
+        let mut header = [0u8; BLOCK];
+        header[..path.len()].copy_from_slice(path.as_bytes());
+        octal(&mut header[100..108], 0o644);
+        octal(&mut header[108..116], 0);
+        octal(&mut header[116..124], 0);
+        octal(&mut header[124..136], data.len() as u64);
+        octal(&mut header[136..148], self.mtime);
+        header[156] = b'0';
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+
+        // checksum is computed with its own field set to spaces
+        header[148..156].fill(b' ');
+        let sum: u32 = header.iter().map(|&b| u32::from(b)).sum();
+        header[148..155].copy_from_slice(format!("{sum:06o}\0").as_bytes());
+
+        self.inner.write_all(&header)?;
+        self.inner.write_all(data)?;
+        let pad = (BLOCK - data.len() % BLOCK) % BLOCK;
+        self.inner.write_all(&[0u8; BLOCK][..pad])
+    }
+
+    /// Write the end-of-archive marker and return the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.inner.write_all(&[0u8; 2 * BLOCK])?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+/// Zero-padded octal, NUL-terminated, filling `field`.
+fn octal(field: &mut [u8], value: u64) {
+    let digits = field.len() - 1;
+    field.copy_from_slice(format!("{value:0digits$o}\0").as_bytes());
+}
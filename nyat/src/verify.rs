@@ -0,0 +1,49 @@
+//! `verify-peer`: periodically check that a peer nyat answers on its
+//! published address.
+use std::convert::Infallible;
+use std::time::Duration;
+
+use nyat_core::mapper::Mapper;
+use nyat_core::net::{LocalAddr, RemoteAddr};
+
+const INTERVAL: Duration = Duration::from_secs(60);
+
+/// Verify `peer` from `local` every [`INTERVAL`] over the mapper's
+/// protocol, reporting reachability changes on stderr. Never returns; pends
+/// forever without a peer.
+pub(crate) async fn watch(
+    prefix: &str,
+    peer: Option<RemoteAddr>,
+    local: LocalAddr,
+    mapper: &Mapper,
+) -> Infallible {
+    let Some(peer) = peer else {
+        return std::future::pending().await;
+    };
+    let udp = matches!(mapper, Mapper::Udp(_));
+
+    let mut reachable = None;
+    let mut interval = tokio::time::interval(INTERVAL);
+    loop {
+        interval.tick().await;
+        let res = if udp {
+            nyat_core::verify::udp(&local, &peer).await
+        } else {
+            nyat_core::verify::tcp(&local, &peer).await
+        };
+        match res {
+            Ok(rtt) if reachable != Some(true) => {
+                reachable = Some(true);
+                eprintln!("{prefix} verify-peer {peer}: reachable ({rtt:?})");
+            }
+            Err(e) if reachable != Some(false) => {
+                reachable = Some(false);
+                eprintln!(
+                    "{prefix} verify-peer {peer}: unreachable: {:#}",
+                    anyhow::Error::from(e)
+                );
+            }
+            _ => {}
+        }
+    }
+}